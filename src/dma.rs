@@ -0,0 +1,871 @@
+//! Direct memory access (DMA) controllers
+//!
+//! Each of the two controllers has eight streams. A stream serves one peripheral request at a
+//! time, selected by its channel; which peripheral is connected to which stream and channel is
+//! fixed by the hardware and encoded by the `Target` implementations of the drivers.
+//!
+//! A `Transfer` takes ownership of the stream, the peripheral and a `'static` buffer for as long
+//! as the hardware may access them, so none of them can be touched until the transfer has been
+//! waited for or aborted. Dropping a transfer stops it. Buffers must hold between 1 and 65535 data
+//! items.
+//!
+//! The data cache of the Cortex-M7 isn't coherent with the DMA controllers, and the transfers
+//! only keep the compiler from reordering memory accesses across their start and end. If the
+//! data cache is enabled, buffers in cacheable memory have to be cleaned before they are
+//! written to a peripheral and invalidated after they have been read into, e.g. with
+//! `SCB::clean_dcache_by_address` and `SCB::invalidate_dcache_by_address`. Alternatively, place
+//! the buffers in a region the MPU configures as non-cacheable, or in the DTCM.
+
+use core::marker::PhantomData;
+use core::mem::ManuallyDrop;
+use core::sync::atomic::{self, Ordering};
+use core::{ptr, slice};
+
+use rcc::AHB1;
+
+/// Extension trait to split a DMA peripheral into independent streams
+pub trait DmaExt {
+    /// The streams to split the DMA into
+    type Streams;
+
+    /// Splits the DMA peripheral into streams, enabling its clock
+    fn split(self, ahb1: &mut AHB1) -> Self::Streams;
+}
+
+/// Interrupt event and status flag of a stream
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    /// Half of the data items have been transferred
+    HalfTransfer,
+    /// All data items have been transferred
+    TransferComplete,
+    /// A bus error occurred, the stream has been disabled
+    TransferError,
+    /// The peripheral request came too early or too late in direct mode
+    DirectModeError,
+    /// The FIFO was overrun or underrun
+    FifoError,
+}
+
+impl Event {
+    /// Bit of the event in the flags of stream 0
+    fn flag(self) -> u32 {
+        match self {
+            Event::FifoError => 1 << 0,
+            Event::DirectModeError => 1 << 2,
+            Event::TransferError => 1 << 3,
+            Event::HalfTransfer => 1 << 4,
+            Event::TransferComplete => 1 << 5,
+        }
+    }
+}
+
+/// DMA transfer error
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Error {
+    /// A bus error occurred
+    Transfer,
+    /// The peripheral request came too early or too late in direct mode
+    DirectMode,
+    /// A buffer is empty or holds more than 65535 data items, or the buffers of a double buffered
+    /// transfer differ in length
+    BufferLength,
+}
+
+/// Priority of a stream relative to the other streams of the same controller
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Priority {
+    Low = 0b00,
+    Medium = 0b01,
+    High = 0b10,
+    VeryHigh = 0b11,
+}
+
+/// Fill level of the FIFO at which it is drained to or refilled from memory
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FifoThreshold {
+    Quarter = 0b00,
+    Half = 0b01,
+    ThreeQuarters = 0b10,
+    Full = 0b11,
+}
+
+/// Number of beats of a burst transfer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Burst {
+    Single = 0b00,
+    Incr4 = 0b01,
+    Incr8 = 0b10,
+    Incr16 = 0b11,
+}
+
+/// Stream configuration
+#[derive(Clone, Copy, Debug)]
+pub struct Config {
+    priority: Priority,
+    circular: bool,
+    fifo: Option<FifoThreshold>,
+    memory_burst: Burst,
+    peripheral_burst: Burst,
+}
+
+impl Config {
+    /// Sets the priority of the stream
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Restarts the transfer from the beginning of the buffer once it is complete
+    pub fn circular(mut self) -> Self {
+        self.circular = true;
+        self
+    }
+
+    /// Buffers data items in the FIFO instead of transferring them directly
+    ///
+    /// The FIFO is required for bursts and is flushed once the `threshold` is reached.
+    pub fn fifo(mut self, threshold: FifoThreshold) -> Self {
+        self.fifo = Some(threshold);
+        self
+    }
+
+    /// Sets the burst size of memory accesses, only used together with the FIFO
+    pub fn memory_burst(mut self, burst: Burst) -> Self {
+        self.memory_burst = burst;
+        self
+    }
+
+    /// Sets the burst size of peripheral accesses, only used together with the FIFO
+    pub fn peripheral_burst(mut self, burst: Burst) -> Self {
+        self.peripheral_burst = burst;
+        self
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            priority: Priority::Medium,
+            circular: false,
+            fifo: None,
+            memory_burst: Burst::Single,
+            peripheral_burst: Burst::Single,
+        }
+    }
+}
+
+/// Direction of a transfer from a peripheral into memory
+pub struct PeripheralToMemory;
+
+/// Direction of a transfer from memory to a peripheral
+pub struct MemoryToPeripheral;
+
+/// Channel of a stream, selecting the peripheral request it serves
+pub trait Channel {
+    /// Value of the `CHSEL` field
+    const CHSEL: u32;
+}
+
+macro_rules! channels {
+    ($($CX:ident: $chsel:expr,)+) => {
+        $(
+            /// DMA channel
+            pub struct $CX;
+
+            impl Channel for $CX {
+                const CHSEL: u32 = $chsel;
+            }
+        )+
+    }
+}
+
+channels! {
+    C0: 0,
+    C1: 1,
+    C2: 2,
+    C3: 3,
+    C4: 4,
+    C5: 5,
+    C6: 6,
+    C7: 7,
+}
+
+/// Size of a data item
+pub trait Word {
+    /// Value of the `PSIZE` and `MSIZE` fields
+    const SIZE: u32;
+}
+
+impl Word for u8 {
+    const SIZE: u32 = 0b00;
+}
+
+impl Word for u16 {
+    const SIZE: u32 = 0b01;
+}
+
+impl Word for u32 {
+    const SIZE: u32 = 0b10;
+}
+
+/// A peripheral data register that `STREAM` can transfer data items in direction `DIR` with
+///
+/// # Safety
+///
+/// `address` must return the address of a data register of the peripheral that is connected to
+/// `STREAM` through `Channel`, and accept accesses of the size of `Word`.
+pub unsafe trait Target<STREAM, DIR> {
+    /// Channel connecting the peripheral request to the stream
+    type Channel: Channel;
+    /// Size of the data items
+    type Word: Word;
+
+    /// Returns the address of the peripheral data register
+    fn address(&self) -> u32;
+}
+
+/// Memory a transfer can take data items from
+///
+/// # Safety
+///
+/// The returned memory must stay valid and must not move for as long as `self` exists. A transfer
+/// that is leaked keeps running, so the memory also has to stay valid if `self` is leaked.
+pub unsafe trait ReadBuffer {
+    /// Size of the data items
+    type Word: Word;
+
+    /// Returns a pointer to the first data item and the number of data items
+    fn read_buffer(&self) -> (*const Self::Word, usize);
+}
+
+/// Memory a transfer can store data items in
+///
+/// # Safety
+///
+/// The returned memory must stay valid and must not move for as long as `self` exists. A transfer
+/// that is leaked keeps running, so the memory also has to stay valid if `self` is leaked.
+pub unsafe trait WriteBuffer {
+    /// Size of the data items
+    type Word: Word;
+
+    /// Returns a pointer to the first data item and the number of data items
+    fn write_buffer(&mut self) -> (*mut Self::Word, usize);
+}
+
+unsafe impl<W: Word> ReadBuffer for &'static [W] {
+    type Word = W;
+
+    fn read_buffer(&self) -> (*const W, usize) {
+        (self.as_ptr(), self.len())
+    }
+}
+
+unsafe impl<W: Word, const N: usize> ReadBuffer for &'static [W; N] {
+    type Word = W;
+
+    fn read_buffer(&self) -> (*const W, usize) {
+        (self.as_ptr(), N)
+    }
+}
+
+unsafe impl<W: Word> ReadBuffer for &'static mut [W] {
+    type Word = W;
+
+    fn read_buffer(&self) -> (*const W, usize) {
+        (self.as_ptr(), self.len())
+    }
+}
+
+unsafe impl<W: Word, const N: usize> ReadBuffer for &'static mut [W; N] {
+    type Word = W;
+
+    fn read_buffer(&self) -> (*const W, usize) {
+        (self.as_ptr(), N)
+    }
+}
+
+unsafe impl<W: Word> WriteBuffer for &'static mut [W] {
+    type Word = W;
+
+    fn write_buffer(&mut self) -> (*mut W, usize) {
+        (self.as_mut_ptr(), self.len())
+    }
+}
+
+unsafe impl<W: Word, const N: usize> WriteBuffer for &'static mut [W; N] {
+    type Word = W;
+
+    fn write_buffer(&mut self) -> (*mut W, usize) {
+        (self.as_mut_ptr(), N)
+    }
+}
+
+/// A DMA stream
+///
+/// Implemented by the stream types of the `dma1` and `dma2` modules. These are the raw register
+/// accesses `Transfer` is built on; the stream must be disabled while it's reconfigured.
+pub trait Stream {
+    /// Returns the status flags, shifted to the positions of stream 0
+    fn flags(&self) -> u32;
+
+    /// Clears status flags given at the positions of stream 0
+    fn clear_flags(&mut self, flags: u32);
+
+    /// Returns the configuration register
+    fn cr(&self) -> u32;
+
+    /// Writes the configuration register
+    fn set_cr(&mut self, bits: u32);
+
+    /// Returns the FIFO control register
+    fn fcr(&self) -> u32;
+
+    /// Writes the FIFO control register
+    fn set_fcr(&mut self, bits: u32);
+
+    /// Returns the number of data items left to transfer
+    fn ndtr(&self) -> u16;
+
+    /// Sets the number of data items to transfer
+    fn set_ndtr(&mut self, items: u16);
+
+    /// Sets the peripheral and both memory addresses
+    fn set_addresses(&mut self, peripheral: u32, memory0: u32, memory1: u32);
+
+    /// Disables the stream and waits until ongoing accesses have finished
+    fn disable(&mut self) {
+        let cr = self.cr();
+        self.set_cr(cr & !CR_EN);
+        while self.cr() & CR_EN != 0 {}
+    }
+}
+
+// Bits of the SxCR register
+const CR_EN: u32 = 1 << 0;
+const CR_DMEIE: u32 = 1 << 1;
+const CR_TEIE: u32 = 1 << 2;
+const CR_HTIE: u32 = 1 << 3;
+const CR_TCIE: u32 = 1 << 4;
+const CR_MINC: u32 = 1 << 10;
+const CR_CIRC: u32 = 1 << 8;
+const CR_DBM: u32 = 1 << 18;
+const CR_CT: u32 = 1 << 19;
+
+// Bits of the SxFCR register
+const FCR_DMDIS: u32 = 1 << 2;
+const FCR_FEIE: u32 = 1 << 7;
+
+/// All status flags of a stream
+const FLAGS: u32 = 0b11_1101;
+
+/// An ongoing DMA transfer between a peripheral and one or two buffers
+///
+/// Dropping the transfer stops it.
+pub struct Transfer<STREAM, TARGET, BUFFER, DIR>
+where
+    STREAM: Stream,
+{
+    stream: STREAM,
+    target: TARGET,
+    buffer: BUFFER,
+    double_buffer: Option<BUFFER>,
+    _direction: PhantomData<DIR>,
+}
+
+impl<STREAM, TARGET, BUFFER> Transfer<STREAM, TARGET, BUFFER, MemoryToPeripheral>
+where
+    STREAM: Stream,
+    TARGET: Target<STREAM, MemoryToPeripheral>,
+    BUFFER: ReadBuffer<Word = TARGET::Word>,
+{
+    /// Starts writing the contents of `buffer` to the peripheral
+    ///
+    /// Fails with `Error::BufferLength` if `buffer` is empty or too long, returning the resources.
+    #[allow(clippy::type_complexity)]
+    pub fn write(
+        stream: STREAM,
+        target: TARGET,
+        buffer: BUFFER,
+        config: Config,
+    ) -> Result<Self, (Error, STREAM, TARGET, BUFFER)> {
+        let (address, len) = buffer.read_buffer();
+        if !is_valid_len(len) {
+            return Err((Error::BufferLength, stream, target, buffer));
+        }
+
+        Ok(Transfer::start(
+            stream,
+            target,
+            buffer,
+            None,
+            address as u32,
+            0,
+            len,
+            0b01,
+            config,
+        ))
+    }
+
+    /// Starts writing the contents of two buffers to the peripheral in turns
+    ///
+    /// The transfer is circular: once a buffer has been written, the stream continues with the
+    /// other one, so the inactive buffer can be refilled in the meantime. Fails with
+    /// `Error::BufferLength` unless both buffers have the same, valid length.
+    #[allow(clippy::type_complexity)]
+    pub fn write_double_buffered(
+        stream: STREAM,
+        target: TARGET,
+        buffers: (BUFFER, BUFFER),
+        config: Config,
+    ) -> Result<Self, (Error, STREAM, TARGET, (BUFFER, BUFFER))> {
+        let (address0, len) = buffers.0.read_buffer();
+        let (address1, len1) = buffers.1.read_buffer();
+        if !is_valid_len(len) || len != len1 {
+            return Err((Error::BufferLength, stream, target, buffers));
+        }
+
+        Ok(Transfer::start(
+            stream,
+            target,
+            buffers.0,
+            Some(buffers.1),
+            address0 as u32,
+            address1 as u32,
+            len,
+            0b01,
+            config,
+        ))
+    }
+}
+
+impl<STREAM, TARGET, BUFFER> Transfer<STREAM, TARGET, BUFFER, PeripheralToMemory>
+where
+    STREAM: Stream,
+    TARGET: Target<STREAM, PeripheralToMemory>,
+    BUFFER: WriteBuffer<Word = TARGET::Word>,
+{
+    /// Starts reading data from the peripheral into `buffer`
+    ///
+    /// Fails with `Error::BufferLength` if `buffer` is empty or too long, returning the resources.
+    #[allow(clippy::type_complexity)]
+    pub fn read(
+        stream: STREAM,
+        target: TARGET,
+        mut buffer: BUFFER,
+        config: Config,
+    ) -> Result<Self, (Error, STREAM, TARGET, BUFFER)> {
+        let (address, len) = buffer.write_buffer();
+        if !is_valid_len(len) {
+            return Err((Error::BufferLength, stream, target, buffer));
+        }
+
+        Ok(Transfer::start(
+            stream,
+            target,
+            buffer,
+            None,
+            address as u32,
+            0,
+            len,
+            0b00,
+            config,
+        ))
+    }
+
+    /// Starts reading data from the peripheral into two buffers in turns
+    ///
+    /// The transfer is circular: once a buffer has been filled, the stream continues with the
+    /// other one, so the data of the inactive buffer can be processed in the meantime. Fails with
+    /// `Error::BufferLength` unless both buffers have the same, valid length.
+    #[allow(clippy::type_complexity)]
+    pub fn read_double_buffered(
+        stream: STREAM,
+        target: TARGET,
+        mut buffers: (BUFFER, BUFFER),
+        config: Config,
+    ) -> Result<Self, (Error, STREAM, TARGET, (BUFFER, BUFFER))> {
+        let (address0, len) = buffers.0.write_buffer();
+        let (address1, len1) = buffers.1.write_buffer();
+        if !is_valid_len(len) || len != len1 {
+            return Err((Error::BufferLength, stream, target, buffers));
+        }
+
+        Ok(Transfer::start(
+            stream,
+            target,
+            buffers.0,
+            Some(buffers.1),
+            address0 as u32,
+            address1 as u32,
+            len,
+            0b00,
+            config,
+        ))
+    }
+}
+
+impl<STREAM, TARGET, BUFFER, DIR> Transfer<STREAM, TARGET, BUFFER, DIR>
+where
+    STREAM: Stream,
+    TARGET: Target<STREAM, DIR>,
+{
+    #[allow(clippy::too_many_arguments)]
+    fn start(
+        mut stream: STREAM,
+        target: TARGET,
+        buffer: BUFFER,
+        double_buffer: Option<BUFFER>,
+        memory0: u32,
+        memory1: u32,
+        len: usize,
+        dir: u32,
+        config: Config,
+    ) -> Self {
+        stream.disable();
+        stream.clear_flags(FLAGS);
+
+        stream.set_addresses(target.address(), memory0, memory1);
+        stream.set_ndtr(len as u16);
+        stream.set_fcr(match config.fifo {
+            Some(threshold) => FCR_DMDIS | threshold as u32,
+            None => 0,
+        });
+
+        let size = <TARGET::Word as Word>::SIZE;
+        let mut cr = (<TARGET::Channel as Channel>::CHSEL << 25)
+            | ((config.memory_burst as u32) << 23)
+            | ((config.peripheral_burst as u32) << 21)
+            | ((config.priority as u32) << 16)
+            | (size << 13)
+            | (size << 11)
+            | CR_MINC
+            | (dir << 6);
+        if config.circular {
+            cr |= CR_CIRC;
+        }
+        if double_buffer.is_some() {
+            cr |= CR_DBM | CR_CIRC;
+        }
+        stream.set_cr(cr);
+
+        // Make sure the buffer contents are written before the stream can access them
+        atomic::compiler_fence(Ordering::Release);
+
+        stream.set_cr(cr | CR_EN);
+
+        Transfer {
+            stream,
+            target,
+            buffer,
+            double_buffer,
+            _direction: PhantomData,
+        }
+    }
+
+    /// Returns `true` if the status flag of `event` is set
+    pub fn is_set(&self, event: Event) -> bool {
+        self.stream.flags() & event.flag() != 0
+    }
+
+    /// Clears the status flag of `event`
+    pub fn clear(&mut self, event: Event) {
+        self.stream.clear_flags(event.flag());
+    }
+
+    /// Returns `true` once all data items have been transferred
+    ///
+    /// Circular transfers set this each time they wrap around.
+    pub fn is_complete(&self) -> bool {
+        self.is_set(Event::TransferComplete)
+    }
+
+    /// Returns `true` once half of the data items have been transferred
+    pub fn is_half_complete(&self) -> bool {
+        self.is_set(Event::HalfTransfer)
+    }
+
+    /// Starts listening for an interrupt event
+    pub fn listen(&mut self, event: Event) {
+        self.set_interrupt(event, true);
+    }
+
+    /// Stops listening for an interrupt event
+    pub fn unlisten(&mut self, event: Event) {
+        self.set_interrupt(event, false);
+    }
+
+    fn set_interrupt(&mut self, event: Event, enable: bool) {
+        let bit = match event {
+            Event::FifoError => {
+                let fcr = self.stream.fcr();
+                let fcr = if enable {
+                    fcr | FCR_FEIE
+                } else {
+                    fcr & !FCR_FEIE
+                };
+                self.stream.set_fcr(fcr);
+                return;
+            }
+            Event::DirectModeError => CR_DMEIE,
+            Event::TransferError => CR_TEIE,
+            Event::HalfTransfer => CR_HTIE,
+            Event::TransferComplete => CR_TCIE,
+        };
+
+        let cr = self.stream.cr();
+        self.stream
+            .set_cr(if enable { cr | bit } else { cr & !bit });
+    }
+
+    /// Returns the number of data items left to transfer into the current buffer
+    pub fn remaining(&self) -> u16 {
+        self.stream.ndtr()
+    }
+
+    /// Returns the index of the buffer the stream is currently transferring, `0` or `1`
+    pub fn current_buffer(&self) -> usize {
+        if self.stream.cr() & CR_CT == 0 {
+            0
+        } else {
+            1
+        }
+    }
+
+    /// Blocks until the transfer is complete and releases the stream, the peripheral and the
+    /// buffer
+    ///
+    /// Circular transfers are stopped once they wrap around for the first time. On error the
+    /// transfer is stopped and the resources are returned along with the error.
+    #[allow(clippy::type_complexity)]
+    pub fn wait(self) -> Result<(STREAM, TARGET, BUFFER), (Error, STREAM, TARGET, BUFFER)> {
+        let error = loop {
+            let flags = self.stream.flags();
+            if flags & Event::TransferError.flag() != 0 {
+                break Some(Error::Transfer);
+            } else if flags & Event::DirectModeError.flag() != 0 {
+                break Some(Error::DirectMode);
+            } else if flags & Event::TransferComplete.flag() != 0 {
+                break None;
+            }
+        };
+
+        let (stream, target, buffer, _) = self.abort_inner();
+        match error {
+            None => Ok((stream, target, buffer)),
+            Some(error) => Err((error, stream, target, buffer)),
+        }
+    }
+
+    /// Stops the transfer and releases the stream, the peripheral and the buffers
+    ///
+    /// The second buffer is only returned for double buffered transfers.
+    pub fn abort(self) -> (STREAM, TARGET, BUFFER, Option<BUFFER>) {
+        self.abort_inner()
+    }
+
+    fn abort_inner(mut self) -> (STREAM, TARGET, BUFFER, Option<BUFFER>) {
+        self.stop();
+
+        // The transfer is stopped already, so it's moved out of without running `drop`
+        let transfer = ManuallyDrop::new(self);
+        unsafe {
+            (
+                ptr::read(&transfer.stream),
+                ptr::read(&transfer.target),
+                ptr::read(&transfer.buffer),
+                ptr::read(&transfer.double_buffer),
+            )
+        }
+    }
+}
+
+impl<STREAM, TARGET, BUFFER, DIR> Transfer<STREAM, TARGET, BUFFER, DIR>
+where
+    STREAM: Stream,
+{
+    fn stop(&mut self) {
+        self.stream.disable();
+        self.stream.clear_flags(FLAGS);
+
+        // Make sure the buffer contents are read only after the stream stopped writing them
+        atomic::compiler_fence(Ordering::Acquire);
+    }
+}
+
+impl<STREAM, TARGET, BUFFER, DIR> Transfer<STREAM, TARGET, BUFFER, DIR>
+where
+    STREAM: Stream,
+    BUFFER: WriteBuffer,
+{
+    /// Returns the contents of the buffer the stream isn't currently transferring, or `None` if
+    /// the transfer isn't double buffered
+    ///
+    /// The stream switches buffers on its own, so the buffer must be refilled or processed
+    /// before the current one is complete.
+    pub fn inactive_buffer(&mut self) -> Option<&mut [BUFFER::Word]> {
+        atomic::compiler_fence(Ordering::Acquire);
+
+        let buffer = match (self.stream.cr() & CR_CT, self.double_buffer.as_mut()) {
+            (_, None) => return None,
+            (0, Some(buffer)) => buffer,
+            (_, Some(_)) => &mut self.buffer,
+        };
+
+        // The handle itself stays borrowed, so it can't be replaced while the stream uses it
+        let (address, len) = buffer.write_buffer();
+        Some(unsafe { slice::from_raw_parts_mut(address, len) })
+    }
+}
+
+impl<STREAM, TARGET, BUFFER, DIR> Drop for Transfer<STREAM, TARGET, BUFFER, DIR>
+where
+    STREAM: Stream,
+{
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Returns `true` if a buffer of `len` data items can be transferred
+///
+/// The stream never completes without data items, and counts at most 65535 of them.
+fn is_valid_len(len: usize) -> bool {
+    len > 0 && len <= usize::from(u16::MAX)
+}
+
+macro_rules! dma {
+    ($($DMAX:ident: ($dmaX:ident, [
+        $($SX:ident: (
+            $sx:ident,
+            $sxcr:ident,
+            $sxndtr:ident,
+            $sxpar:ident,
+            $sxm0ar:ident,
+            $sxm1ar:ident,
+            $sxfcr:ident,
+            $isr:ident,
+            $ifcr:ident,
+            $shift:expr
+        ),)+
+    ]),)+) => {
+        $(
+            /// DMA controller
+            pub mod $dmaX {
+                use stm32::$DMAX;
+
+                use rcc::{Enable, AHB1};
+                use super::{DmaExt, Stream};
+
+                /// DMA streams
+                pub struct Streams {
+                    $(
+                        /// Stream
+                        pub $sx: $SX,
+                    )+
+                }
+
+                impl DmaExt for $DMAX {
+                    type Streams = Streams;
+
+                    fn split(self, ahb1: &mut AHB1) -> Streams {
+                        $DMAX::enable(ahb1);
+
+                        Streams {
+                            $(
+                                $sx: $SX { _0: () },
+                            )+
+                        }
+                    }
+                }
+
+                $(
+                    /// Stream
+                    pub struct $SX {
+                        _0: (),
+                    }
+
+                    impl Stream for $SX {
+                        fn flags(&self) -> u32 {
+                            // NOTE(unsafe) atomic read with no side effects
+                            unsafe { ((*$DMAX::ptr()).$isr.read().bits() >> $shift) & super::FLAGS }
+                        }
+
+                        fn clear_flags(&mut self, flags: u32) {
+                            // NOTE(unsafe) atomic write to a stateless register, only clearing
+                            // the flags of this stream
+                            unsafe {
+                                (*$DMAX::ptr())
+                                    .$ifcr
+                                    .write(|w| w.bits((flags & super::FLAGS) << $shift))
+                            }
+                        }
+
+                        fn cr(&self) -> u32 {
+                            // NOTE(unsafe) this stream owns its registers
+                            unsafe { (*$DMAX::ptr()).$sxcr.read().bits() }
+                        }
+
+                        fn set_cr(&mut self, bits: u32) {
+                            // NOTE(unsafe) this stream owns its registers
+                            unsafe { (*$DMAX::ptr()).$sxcr.write(|w| w.bits(bits)) }
+                        }
+
+                        fn fcr(&self) -> u32 {
+                            // NOTE(unsafe) this stream owns its registers
+                            unsafe { (*$DMAX::ptr()).$sxfcr.read().bits() }
+                        }
+
+                        fn set_fcr(&mut self, bits: u32) {
+                            // NOTE(unsafe) this stream owns its registers
+                            unsafe { (*$DMAX::ptr()).$sxfcr.write(|w| w.bits(bits)) }
+                        }
+
+                        fn ndtr(&self) -> u16 {
+                            // NOTE(unsafe) this stream owns its registers
+                            unsafe { (*$DMAX::ptr()).$sxndtr.read().bits() as u16 }
+                        }
+
+                        fn set_ndtr(&mut self, items: u16) {
+                            // NOTE(unsafe) this stream owns its registers
+                            unsafe { (*$DMAX::ptr()).$sxndtr.write(|w| w.bits(u32::from(items))) }
+                        }
+
+                        fn set_addresses(&mut self, peripheral: u32, memory0: u32, memory1: u32) {
+                            // NOTE(unsafe) this stream owns its registers
+                            unsafe {
+                                let dma = &*$DMAX::ptr();
+                                dma.$sxpar.write(|w| w.bits(peripheral));
+                                dma.$sxm0ar.write(|w| w.bits(memory0));
+                                dma.$sxm1ar.write(|w| w.bits(memory1));
+                            }
+                        }
+                    }
+                )+
+            }
+        )+
+    }
+}
+
+dma! {
+    DMA1: (dma1, [
+        S0: (s0, s0cr, s0ndtr, s0par, s0m0ar, s0m1ar, s0fcr, lisr, lifcr, 0),
+        S1: (s1, s1cr, s1ndtr, s1par, s1m0ar, s1m1ar, s1fcr, lisr, lifcr, 6),
+        S2: (s2, s2cr, s2ndtr, s2par, s2m0ar, s2m1ar, s2fcr, lisr, lifcr, 16),
+        S3: (s3, s3cr, s3ndtr, s3par, s3m0ar, s3m1ar, s3fcr, lisr, lifcr, 22),
+        S4: (s4, s4cr, s4ndtr, s4par, s4m0ar, s4m1ar, s4fcr, hisr, hifcr, 0),
+        S5: (s5, s5cr, s5ndtr, s5par, s5m0ar, s5m1ar, s5fcr, hisr, hifcr, 6),
+        S6: (s6, s6cr, s6ndtr, s6par, s6m0ar, s6m1ar, s6fcr, hisr, hifcr, 16),
+        S7: (s7, s7cr, s7ndtr, s7par, s7m0ar, s7m1ar, s7fcr, hisr, hifcr, 22),
+    ]),
+    DMA2: (dma2, [
+        S0: (s0, s0cr, s0ndtr, s0par, s0m0ar, s0m1ar, s0fcr, lisr, lifcr, 0),
+        S1: (s1, s1cr, s1ndtr, s1par, s1m0ar, s1m1ar, s1fcr, lisr, lifcr, 6),
+        S2: (s2, s2cr, s2ndtr, s2par, s2m0ar, s2m1ar, s2fcr, lisr, lifcr, 16),
+        S3: (s3, s3cr, s3ndtr, s3par, s3m0ar, s3m1ar, s3fcr, lisr, lifcr, 22),
+        S4: (s4, s4cr, s4ndtr, s4par, s4m0ar, s4m1ar, s4fcr, hisr, hifcr, 0),
+        S5: (s5, s5cr, s5ndtr, s5par, s5m0ar, s5m1ar, s5fcr, hisr, hifcr, 6),
+        S6: (s6, s6cr, s6ndtr, s6par, s6m0ar, s6m1ar, s6fcr, hisr, hifcr, 16),
+        S7: (s7, s7cr, s7ndtr, s7par, s7m0ar, s7m1ar, s7fcr, hisr, hifcr, 22),
+    ]),
+}
@@ -2,13 +2,15 @@
 
 use core::marker::PhantomData;
 
+use rcc::AHB1;
+
 /// Extension trait to split a GPIO peripheral in independent pins and registers
 pub trait GpioExt {
     /// The parts to split the GPIO into
     type Parts;
 
     /// Splits the GPIO block into independent pins and registers
-    fn split(self) -> Self::Parts;
+    fn split(self, ahb1: &mut AHB1) -> Self::Parts;
 }
 
 pub struct AF0;
@@ -66,7 +68,7 @@ pub enum Speed {
 }
 
 macro_rules! gpio {
-    ($GPIOX:ident, $gpiox:ident, $iopxenr:ident, $PXx:ident, [
+    ($GPIOX:ident, $gpiox:ident, $PXx:ident, [
         $($PXi:ident: ($pxi:ident, $i:expr, $MODE:ty),)+
     ]) => {
         /// GPIO
@@ -76,7 +78,7 @@ macro_rules! gpio {
             use hal::digital::{InputPin, OutputPin};
             use stm32::$GPIOX;
 
-            use stm32::RCC;
+            use rcc::{Enable, AHB1};
             use super::{
                 Alternate, Floating, GpioExt, Input, OpenDrain, Output, Speed,
                 PullDown, PullUp, PushPull, AF0, AF1, AF2, AF3, AF4, AF5, AF6, AF7, AF8, AF9, AF10,
@@ -94,10 +96,8 @@ macro_rules! gpio {
             impl GpioExt for $GPIOX {
                 type Parts = Parts;
 
-                fn split(self) -> Parts {
-                    // NOTE(unsafe) This executes only during initialisation
-                    let rcc = unsafe { &(*RCC::ptr()) };
-                    rcc.ahb1enr.modify(|_, w| w.$iopxenr().set_bit());
+                fn split(self, ahb1: &mut AHB1) -> Parts {
+                    $GPIOX::enable(ahb1);
 
                     Parts {
                         $(
@@ -522,7 +522,7 @@ macro_rules! gpio {
     feature = "stm32f7x7",
     feature = "stm32f7x9",
 ))]
-gpio!(GPIOA, gpioa, gpioaen, PA, [
+gpio!(GPIOA, gpioa, PA, [
     PA0: (pa0, 0, Input<Floating>),
     PA1: (pa1, 1, Input<Floating>),
     PA2: (pa2, 2, Input<Floating>),
@@ -549,7 +549,7 @@ gpio!(GPIOA, gpioa, gpioaen, PA, [
     feature = "stm32f7x7",
     feature = "stm32f7x9",
 ))]
-gpio!(GPIOB, gpiob, gpioben, PB, [
+gpio!(GPIOB, gpiob, PB, [
     PB0: (pb0, 0, Input<Floating>),
     PB1: (pb1, 1, Input<Floating>),
     PB2: (pb2, 2, Input<Floating>),
@@ -576,7 +576,7 @@ gpio!(GPIOB, gpiob, gpioben, PB, [
     feature = "stm32f7x7",
     feature = "stm32f7x9",
 ))]
-gpio!(GPIOC, gpioc, gpiocen, PC, [
+gpio!(GPIOC, gpioc, PC, [
     PC0: (pc0, 0, Input<Floating>),
     PC1: (pc1, 1, Input<Floating>),
     PC2: (pc2, 2, Input<Floating>),
@@ -603,7 +603,7 @@ gpio!(GPIOC, gpioc, gpiocen, PC, [
     feature = "stm32f7x7",
     feature = "stm32f7x9",
 ))]
-gpio!(GPIOD, gpiod, gpioden, PD, [
+gpio!(GPIOD, gpiod, PD, [
     PD0: (pd0, 0, Input<Floating>),
     PD1: (pd1, 1, Input<Floating>),
     PD2: (pd2, 2, Input<Floating>),
@@ -630,7 +630,7 @@ gpio!(GPIOD, gpiod, gpioden, PD, [
     feature = "stm32f7x7",
     feature = "stm32f7x9",
 ))]
-gpio!(GPIOE, gpioe, gpioeen, PE, [
+gpio!(GPIOE, gpioe, PE, [
     PE0: (pe0, 0, Input<Floating>),
     PE1: (pe1, 1, Input<Floating>),
     PE2: (pe2, 2, Input<Floating>),
@@ -657,7 +657,7 @@ gpio!(GPIOE, gpioe, gpioeen, PE, [
     feature = "stm32f7x7",
     feature = "stm32f7x9",
 ))]
-gpio!(GPIOF, gpiof, gpiofen, PF, [
+gpio!(GPIOF, gpiof, PF, [
     PF0: (pf0, 0, Input<Floating>),
     PF1: (pf1, 1, Input<Floating>),
     PF2: (pf2, 2, Input<Floating>),
@@ -684,7 +684,7 @@ gpio!(GPIOF, gpiof, gpiofen, PF, [
     feature = "stm32f7x7",
     feature = "stm32f7x9",
 ))]
-gpio!(GPIOG, gpiog, gpiogen, PG, [
+gpio!(GPIOG, gpiog, PG, [
     PG0: (pg0, 0, Input<Floating>),
     PG1: (pg1, 1, Input<Floating>),
     PG2: (pg2, 2, Input<Floating>),
@@ -711,7 +711,7 @@ gpio!(GPIOG, gpiog, gpiogen, PG, [
     feature = "stm32f7x7",
     feature = "stm32f7x9",
 ))]
-gpio!(GPIOH, gpioh, gpiohen, PH, [
+gpio!(GPIOH, gpioh, PH, [
     PH0: (ph0, 0, Input<Floating>),
     PH1: (ph1, 1, Input<Floating>),
     PH2: (ph2, 2, Input<Floating>),
@@ -738,7 +738,7 @@ gpio!(GPIOH, gpioh, gpiohen, PH, [
 //     feature = "stm32f7x7",
 //     feature = "stm32f7x9",
 // ))]
-// gpio!(GPIOH, gpioh, gpiohen, PH, [
+// gpio!(GPIOH, gpioh, PH, [
 //     PH0: (ph0, 0, Input<Floating>),
 //     PH1: (ph1, 1, Input<Floating>),
 // ]);
@@ -751,7 +751,7 @@ gpio!(GPIOH, gpioh, gpiohen, PH, [
     feature = "stm32f7x7",
     feature = "stm32f7x9",
 ))]
-gpio!(GPIOI, gpioi, gpioien, PI, [
+gpio!(GPIOI, gpioi, PI, [
     PI0: (pi0, 0, Input<Floating>),
     PI1: (pi1, 1, Input<Floating>),
     PI2: (pi2, 2, Input<Floating>),
@@ -771,7 +771,7 @@ gpio!(GPIOI, gpioi, gpioien, PI, [
 ]);
 
 /*
-gpio!(GPIOJ, gpioj, gpiojen, PJ, [
+gpio!(GPIOJ, gpioj, PJ, [
     PJ0: (pj0, 0, Input<Floating>),
     PJ1: (pj1, 1, Input<Floating>),
     PJ2: (pj2, 2, Input<Floating>),
@@ -790,7 +790,7 @@ gpio!(GPIOJ, gpioj, gpiojen, PJ, [
     PJ15: (pj15, 15, Input<Floating>),
 ]);
 
-gpio!(GPIOK, gpiok, gpioken, PK, [
+gpio!(GPIOK, gpiok, PK, [
     PK0: (pk0, 0, Input<Floating>),
     PK1: (pk1, 1, Input<Floating>),
     PK2: (pk2, 2, Input<Floating>),
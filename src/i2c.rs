@@ -1,244 +1,1153 @@
-use stm32::{I2C1, RCC};
+use core::cmp;
 
-use hal::blocking::i2c::{Read, Write, WriteRead};
+use stm32::i2c1;
+#[cfg(any(
+    feature = "stm32f7x2",
+    feature = "stm32f7x3",
+    feature = "stm32f7x5",
+    feature = "stm32f7x6",
+    feature = "stm32f7x7",
+    feature = "stm32f7x9",
+))]
+use stm32::{I2C1, I2C2, I2C3};
 
-use gpio::gpiob::{PB6, PB7, PB8, PB9};
+#[cfg(any(
+    feature = "stm32f7x5",
+    feature = "stm32f7x6",
+    feature = "stm32f7x7",
+    feature = "stm32f7x9",
+))]
+use stm32::I2C4;
+
+use hal::blocking::delay::DelayUs;
+use hal::blocking::i2c::{Read, TenBitAddress, Write, WriteIter, WriteIterRead, WriteRead};
+#[allow(deprecated)]
+use hal::digital::{InputPin, OutputPin};
+use nb;
+
+#[cfg(any(
+    feature = "stm32f7x2",
+    feature = "stm32f7x3",
+    feature = "stm32f7x5",
+    feature = "stm32f7x6",
+    feature = "stm32f7x7",
+    feature = "stm32f7x9",
+))]
+use gpio::gpioa::PA8;
+#[cfg(any(
+    feature = "stm32f7x2",
+    feature = "stm32f7x3",
+    feature = "stm32f7x5",
+    feature = "stm32f7x6",
+    feature = "stm32f7x7",
+    feature = "stm32f7x9",
+))]
+use gpio::gpiob::{PB10, PB11, PB6, PB7, PB8, PB9};
+#[cfg(any(
+    feature = "stm32f7x2",
+    feature = "stm32f7x3",
+    feature = "stm32f7x5",
+    feature = "stm32f7x6",
+    feature = "stm32f7x7",
+    feature = "stm32f7x9",
+))]
+use gpio::gpioc::PC9;
+#[cfg(any(
+    feature = "stm32f7x5",
+    feature = "stm32f7x6",
+    feature = "stm32f7x7",
+    feature = "stm32f7x9",
+))]
+use gpio::gpiod::{PD12, PD13};
+#[cfg(any(
+    feature = "stm32f7x2",
+    feature = "stm32f7x3",
+    feature = "stm32f7x5",
+    feature = "stm32f7x6",
+    feature = "stm32f7x7",
+    feature = "stm32f7x9",
+))]
+use gpio::gpiof::{PF0, PF1};
+#[cfg(any(
+    feature = "stm32f7x5",
+    feature = "stm32f7x6",
+    feature = "stm32f7x7",
+    feature = "stm32f7x9",
+))]
+use gpio::gpiof::{PF14, PF15};
+#[cfg(any(
+    feature = "stm32f7x5",
+    feature = "stm32f7x6",
+    feature = "stm32f7x7",
+    feature = "stm32f7x9",
+))]
+use gpio::gpioh::{PH11, PH12};
+#[cfg(any(
+    feature = "stm32f7x2",
+    feature = "stm32f7x3",
+    feature = "stm32f7x5",
+    feature = "stm32f7x6",
+    feature = "stm32f7x7",
+    feature = "stm32f7x9",
+))]
+use gpio::gpioh::{PH4, PH5, PH7, PH8};
 use gpio::{Alternate, AF4};
-use rcc::Clocks;
-use time::{Hertz, KiloHertz, U32Ext};
+use rcc::{Clocks, Enable, Reset, APB1};
+use time::{Hertz, KiloHertz};
 
 /// I2C abstraction
 pub struct I2c<I2C, PINS> {
     i2c: I2C,
     pins: PINS,
+    i2cclk: Hertz,
+    sysclk: Hertz,
+    /// Kernel clock cycles of an SCL period
+    scl_cycles: u32,
+    /// SCL low timeout in µs
+    timeout: Option<u32>,
+}
+
+/// I2C slave
+///
+/// SCL is held low while the slave waits for the software to handle an event, so the master
+/// can't overrun it.
+pub struct I2cSlave<I2C, PINS> {
+    i2c: I2C,
+    pins: PINS,
+}
+
+pub trait Pins<I2C> {}
+pub trait PinScl<I2C> {}
+pub trait PinSda<I2C> {}
+
+impl<I2C, SCL, SDA> Pins<I2C> for (SCL, SDA)
+where
+    SCL: PinScl<I2C>,
+    SDA: PinSda<I2C>,
+{
+}
+
+macro_rules! pins {
+    ($($I2CX:ty:
+        SCL: [$($SCL:ty),*]
+        SDA: [$($SDA:ty),*]
+    )+) => {
+        $(
+            $(
+                impl PinScl<$I2CX> for $SCL {}
+            )*
+            $(
+                impl PinSda<$I2CX> for $SDA {}
+            )*
+        )+
+    }
 }
 
-pub trait Pins<I2c> {}
+#[cfg(any(
+    feature = "stm32f7x2",
+    feature = "stm32f7x3",
+    feature = "stm32f7x5",
+    feature = "stm32f7x6",
+    feature = "stm32f7x7",
+    feature = "stm32f7x9",
+))]
+pins! {
+    I2C1:
+        SCL: [
+            PB6<Alternate<AF4>>,
+            PB8<Alternate<AF4>>
+        ]
+        SDA: [
+            PB7<Alternate<AF4>>,
+            PB9<Alternate<AF4>>
+        ]
+
+    I2C2:
+        SCL: [
+            PB10<Alternate<AF4>>,
+            PF1<Alternate<AF4>>,
+            PH4<Alternate<AF4>>
+        ]
+        SDA: [
+            PB11<Alternate<AF4>>,
+            PF0<Alternate<AF4>>,
+            PH5<Alternate<AF4>>
+        ]
+
+    I2C3:
+        SCL: [
+            PA8<Alternate<AF4>>,
+            PH7<Alternate<AF4>>
+        ]
+        SDA: [
+            PC9<Alternate<AF4>>,
+            PH8<Alternate<AF4>>
+        ]
+}
 
-impl Pins<I2C1> for (PB6<Alternate<AF4>>, PB7<Alternate<AF4>>) {}
-impl Pins<I2C1> for (PB8<Alternate<AF4>>, PB9<Alternate<AF4>>) {}
+#[cfg(any(
+    feature = "stm32f7x5",
+    feature = "stm32f7x6",
+    feature = "stm32f7x7",
+    feature = "stm32f7x9",
+))]
+pins! {
+    I2C4:
+        SCL: [
+            PD12<Alternate<AF4>>,
+            PF14<Alternate<AF4>>,
+            PH11<Alternate<AF4>>
+        ]
+        SDA: [
+            PD13<Alternate<AF4>>,
+            PF15<Alternate<AF4>>,
+            PH12<Alternate<AF4>>
+        ]
+}
 
+/// I2C error
 #[derive(Debug)]
 pub enum Error {
-    OVERRUN,
-    NACK,
+    /// A byte was received before the previous one was read
+    Overrun,
+    /// The slave didn't acknowledge its address
+    AddressNack,
+    /// The slave didn't acknowledge a data byte
+    DataNack,
+    /// Misplaced START or STOP condition
+    Bus,
+    /// Another master took over the bus
+    ArbitrationLoss,
+    /// SCL was held low for longer than the timeout, or the bus made no progress at all
+    Timeout,
 }
 
-impl<PINS> I2c<I2C1, PINS> {
-    pub fn i2c1(i2c: I2C1, pins: PINS, speed: KiloHertz, clocks: Clocks) -> Self
-    where
-        PINS: Pins<I2C1>,
-    {
-        let speed: Hertz = speed.into();
+/// Slave address of a transfer
+#[derive(Clone, Copy)]
+enum Address {
+    SevenBit(u8),
+    TenBit(u16),
+}
 
-        // NOTE(unsafe) This executes only during initialisation
-        let rcc = unsafe { &(*RCC::ptr()) };
+/// Own address of an I2C slave
+#[derive(Clone, Copy, Debug)]
+pub enum OwnAddress {
+    SevenBit(u8),
+    TenBit(u16),
+}
 
-        // Enable clock for I2C1
-        rcc.apb1enr.modify(|_, w| w.i2c1en().set_bit());
+/// I2C slave configuration
+pub struct SlaveConfig {
+    /// Own address 1
+    pub address1: OwnAddress,
+    /// Own address 2 and the number of its low bits that are ignored, up to 7
+    pub address2: Option<(u8, u8)>,
+    /// Speed of the bus, which the data setup and hold times are chosen for
+    pub speed: KiloHertz,
+}
 
-        // Reset I2C1
-        rcc.apb1rstr.modify(|_, w| w.i2c1rst().set_bit());
-        rcc.apb1rstr.modify(|_, w| w.i2c1rst().clear_bit());
+impl SlaveConfig {
+    /// Answers to `address` on a bus of up to 100 kHz
+    pub fn new(address: OwnAddress) -> Self {
+        SlaveConfig {
+            address1: address,
+            address2: None,
+            speed: KiloHertz(100),
+        }
+    }
 
-        // Make sure the I2C unit is disabled so we can configure it
-        i2c.cr1.modify(|_, w| w.pe().clear_bit());
+    /// Also answers to the 7-bit `address`, ignoring its `masked_bits` low bits
+    pub fn address2(mut self, address: u8, masked_bits: u8) -> Self {
+        self.address2 = Some((address, masked_bits));
+        self
+    }
 
-        // Calculate settings for I2C speed modes
-        let clock = clocks.pclk1().0;
-        let freq = clock / 1_000_000;
-        assert!(freq >= 2 && freq <= 50);
+    /// Sets the speed of the bus
+    pub fn speed(mut self, speed: KiloHertz) -> Self {
+        self.speed = speed;
+        self
+    }
+}
+
+/// Event of an I2C slave
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SlaveEvent {
+    /// The master addressed the slave to write to it, the bytes follow as `Received`
+    ///
+    /// `address` is the 7-bit address sent by the master, or the header `0b11110xx` of a
+    /// 10-bit address.
+    WriteRequest { address: u8 },
+    /// The master addressed the slave to read from it, followed by `TransmitRequest`s
+    ReadRequest { address: u8 },
+    /// The master wrote a byte
+    Received(u8),
+    /// The master waits for the next byte, which has to be passed to `send`
+    TransmitRequest,
+    /// A STOP condition ended the transfer
+    Stop,
+}
+
+/// The requested bus speed or timeout can't be generated from the I2C kernel clock, or an own
+/// address is out of range
+#[derive(Debug)]
+pub struct InvalidConfig;
+
+/// SCL low timeout set up by the constructors, in µs
+const DEFAULT_TIMEOUT: u32 = 25_000;
+
+/// SYSCLK cycles after which a master gives up on a stuck bus if the SCL low timeout is disabled
+const STUCK_BUS_CYCLES: u64 = 100_000_000;
+
+/// Timing requirements of a speed mode, in picoseconds
+struct ModeTiming {
+    /// Highest SCL frequency in Hz
+    max_speed: u32,
+    data_valid_max: u64,
+    data_setup_min: u64,
+    scl_low_min: u64,
+    scl_high_min: u64,
+    /// Rise and fall times assumed by `Timing::for_speed`
+    rise: u64,
+    fall: u64,
+}
+
+/// Standard mode, fast mode and fast mode plus as defined by the I2C specification
+const MODES: [ModeTiming; 3] = [
+    ModeTiming {
+        max_speed: 100_000,
+        data_valid_max: 3_450_000,
+        data_setup_min: 250_000,
+        scl_low_min: 4_700_000,
+        scl_high_min: 4_000_000,
+        rise: 1_000_000,
+        fall: 300_000,
+    },
+    ModeTiming {
+        max_speed: 400_000,
+        data_valid_max: 900_000,
+        data_setup_min: 100_000,
+        scl_low_min: 1_300_000,
+        scl_high_min: 600_000,
+        rise: 300_000,
+        fall: 300_000,
+    },
+    ModeTiming {
+        max_speed: 1_000_000,
+        data_valid_max: 450_000,
+        data_setup_min: 50_000,
+        scl_low_min: 500_000,
+        scl_high_min: 260_000,
+        rise: 50_000,
+        fall: 20_000,
+    },
+];
 
-        // Configure bus frequency into I2C peripheral
-        i2c.cr2.write(|w| unsafe { w.freq().bits(freq as u8) });
+/// Delay of the analog noise filter in picoseconds
+const FILTER_MIN: u64 = 50_000;
+const FILTER_MAX: u64 = 260_000;
 
-        let trise = if speed <= 100.khz().into() {
-            freq + 1
-        } else {
-            (freq * 300) / 1000 + 1
+/// Fields of the TIMINGR register
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Timing {
+    pub presc: u8,
+    pub scldel: u8,
+    pub sdadel: u8,
+    pub sclh: u8,
+    pub scll: u8,
+}
+
+impl Timing {
+    /// Calculates the timing for an SCL frequency of `speed` from the I2C kernel clock and the
+    /// rise and fall times of the bus lines in ns
+    ///
+    /// Speeds up to 100 kHz use standard mode, up to 400 kHz fast mode and up to 1 MHz fast mode
+    /// plus. SCL runs at `speed` or, if the minimum low and high times of the mode don't leave
+    /// enough room, slower. The analog noise filter is assumed to be enabled.
+    pub fn new(
+        i2cclk: Hertz,
+        speed: Hertz,
+        rise_time: u32,
+        fall_time: u32,
+    ) -> Result<Timing, InvalidConfig> {
+        let mode = match MODES.iter().find(|mode| speed.0 <= mode.max_speed) {
+            Some(mode) if speed.0 != 0 && i2cclk.0 != 0 => mode,
+            _ => return Err(InvalidConfig),
         };
 
-        // Configure correct rise times
-        i2c.trise.write(|w| w.trise().bits(trise as u8));
+        let clk = 1_000_000_000_000 / u64::from(i2cclk.0);
+        let period = 1_000_000_000_000 / u64::from(speed.0);
+        let rise = u64::from(rise_time) * 1000;
+        let fall = u64::from(fall_time) * 1000;
 
-        // I2C clock control calculation
-        if speed <= 100.khz().into() {
-            let ccr = {
-                let ccr = clock / (speed.0 * 2);
-                if ccr < 4 {
-                    4
-                } else {
-                    ccr
-                }
+        // The peripheral only sees the SCL edges after the line changed, the filter delay and
+        // two to three clock cycles of synchronization
+        let sync = rise + fall + 2 * (FILTER_MIN + 2 * clk);
+
+        // Use the finest prescaler all fields fit with
+        for presc in 0..16 {
+            let tpresc = (presc + 1) * clk;
+
+            // Data setup time before the rising edge of SCL
+            let scldel = cmp::max((rise + mode.data_setup_min + tpresc - 1) / tpresc, 1) - 1;
+
+            // Data hold time after the falling edge of SCL, which must end before the data valid
+            // time of the mode passed
+            let sdadel = (fall.saturating_sub(FILTER_MIN + 3 * clk) + tpresc - 1) / tpresc;
+            let sdadel_max = match mode.data_valid_max.checked_sub(rise + FILTER_MAX + 4 * clk) {
+                Some(time) => time / tpresc,
+                None => return Err(InvalidConfig),
             };
 
-            // Set clock to standard mode with appropriate parameters for selected speed
-            i2c.ccr.write(|w| unsafe {
-                w.f_s()
-                    .clear_bit()
-                    .duty()
-                    .clear_bit()
-                    .ccr()
-                    .bits(ccr as u16)
-            });
-        } else {
-            const DUTYCYCLE: u8 = 0;
-            if DUTYCYCLE == 0 {
-                let ccr = clock / (speed.0 * 3);
-                let ccr = if ccr < 1 { 1 } else { ccr };
-
-                // Set clock to fast mode with appropriate parameters for selected speed (2:1 duty cycle)
-                i2c.ccr.write(|w| unsafe {
-                    w.f_s().set_bit().duty().clear_bit().ccr().bits(ccr as u16)
-                });
-            } else {
-                let ccr = clock / (speed.0 * 25);
-                let ccr = if ccr < 1 { 1 } else { ccr };
+            // Split the SCL period in the ratio of the minimum low and high times
+            let low_min = (mode.scl_low_min + tpresc - 1) / tpresc;
+            let high_min = (mode.scl_high_min + tpresc - 1) / tpresc;
+            let total_min = low_min + high_min;
+            let cycles = cmp::max(
+                (period.saturating_sub(sync) + tpresc - 1) / tpresc,
+                total_min,
+            );
+            let low = cmp::max(low_min, (cycles * low_min + total_min - 1) / total_min);
+            let high = cmp::max(high_min, cycles - low);
 
-                // Set clock to fast mode with appropriate parameters for selected speed (16:9 duty cycle)
-                i2c.ccr.write(|w| unsafe {
-                    w.f_s().set_bit().duty().set_bit().ccr().bits(ccr as u16)
+            if scldel <= 0xf && sdadel <= cmp::min(sdadel_max, 0xf) && low <= 0x100 && high <= 0x100
+            {
+                return Ok(Timing {
+                    presc: presc as u8,
+                    scldel: scldel as u8,
+                    sdadel: sdadel as u8,
+                    sclh: (high - 1) as u8,
+                    scll: (low - 1) as u8,
                 });
             }
         }
 
-        // Enable the I2C processing
-        i2c.cr1.modify(|_, w| w.pe().set_bit());
-
-        I2c { i2c, pins }
+        Err(InvalidConfig)
     }
 
-    pub fn release(self) -> (I2C1, PINS) {
-        (self.i2c, self.pins)
+    /// Calculates the timing for an SCL frequency of `speed`, assuming the longest rise and fall
+    /// times standard and fast mode allow
+    ///
+    /// The data hold and data valid times of fast mode plus can't both be met at its longest rise
+    /// and fall times, so 50 ns and 20 ns are assumed instead. This needs a kernel clock above
+    /// 29 MHz.
+    pub fn for_speed(i2cclk: Hertz, speed: Hertz) -> Result<Timing, InvalidConfig> {
+        match MODES.iter().find(|mode| speed.0 <= mode.max_speed) {
+            Some(mode) => Timing::new(
+                i2cclk,
+                speed,
+                (mode.rise / 1000) as u32,
+                (mode.fall / 1000) as u32,
+            ),
+            None => Err(InvalidConfig),
+        }
     }
+}
 
-    fn send_byte(&self, byte: u8) -> Result<(), Error> {
-        // Wait until we're ready for sending
-        while self.i2c.sr1.read().tx_e().bit_is_clear() {}
+/// Writes the bus timing, which can only be changed while the peripheral is disabled
+fn write_timing(i2c: &i2c1::RegisterBlock, timing: Timing) {
+    i2c.timingr.write(|w| {
+        w.presc()
+            .bits(timing.presc)
+            .scldel()
+            .bits(timing.scldel)
+            .sdadel()
+            .bits(timing.sdadel)
+            .sclh()
+            .bits(timing.sclh)
+            .scll()
+            .bits(timing.scll)
+    });
+}
 
-        // Push out a byte of data
-        self.i2c.dr.write(|w| unsafe { w.bits(u32::from(byte)) });
+/// Clears and returns the error flagged in the status, if any
+fn check(i2c: &i2c1::RegisterBlock, isr: &i2c1::isr::R) -> Result<(), Error> {
+    let error = if isr.berr().bit_is_set() {
+        i2c.icr.write(|w| w.berrcf().set_bit());
+        Error::Bus
+    } else if isr.arlo().bit_is_set() {
+        i2c.icr.write(|w| w.arlocf().set_bit());
+        Error::ArbitrationLoss
+    } else if isr.timeout().bit_is_set() {
+        // A master sends a STOP condition on its own
+        i2c.icr.write(|w| w.timoutcf().set_bit());
+        Error::Timeout
+    } else if isr.ovr().bit_is_set() {
+        i2c.icr.write(|w| w.ovrcf().set_bit());
+        Error::Overrun
+    } else {
+        return Ok(());
+    };
 
-        // While until byte is transferred
-        while {
-            let sr1 = self.i2c.sr1.read();
+    // Drop the byte of the aborted transfer
+    i2c.isr.write(|w| w.txe().set_bit());
 
-            // If we received a NACK, then this is an error
-            if sr1.af().bit_is_set() {
-                return Err(Error::NACK);
-            }
+    Err(error)
+}
 
-            sr1.btf().bit_is_clear()
-        } {}
+/// Frees the bus from a slave holding SDA low, e.g. after a reset in the middle of a transfer
+///
+/// Clocks SCL at about 100 kHz until the slave releases SDA, at most nine times, and finishes with
+/// a STOP condition. Both pins have to be open drain outputs, so this runs between `release` and
+/// configuring the I2C again. Returns `Error::Bus` if SDA is still held low afterwards.
+#[allow(deprecated)]
+pub fn recover_bus<SCL, SDA, D>(scl: &mut SCL, sda: &mut SDA, delay: &mut D) -> Result<(), Error>
+where
+    SCL: OutputPin,
+    SDA: OutputPin + InputPin,
+    D: DelayUs<u32>,
+{
+    sda.set_high();
+    scl.set_high();
+    delay.delay_us(5);
 
-        Ok(())
-    }
+    for _ in 0..9 {
+        if sda.is_high() {
+            break;
+        }
 
-    fn recv_byte(&self) -> Result<u8, Error> {
-        while self.i2c.sr1.read().rx_ne().bit_is_clear() {}
-        let value = self.i2c.dr.read().bits() as u8;
-        Ok(value)
+        scl.set_low();
+        delay.delay_us(5);
+        scl.set_high();
+        delay.delay_us(5);
     }
-}
-
-impl<PINS> WriteRead for I2c<I2C1, PINS> {
-    type Error = Error;
 
-    fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Self::Error> {
-        self.write(addr, bytes)?;
-        self.read(addr, buffer)?;
+    // SDA rises while SCL is high
+    scl.set_low();
+    delay.delay_us(5);
+    sda.set_low();
+    delay.delay_us(5);
+    scl.set_high();
+    delay.delay_us(5);
+    sda.set_high();
+    delay.delay_us(5);
 
+    if sda.is_low() {
+        Err(Error::Bus)
+    } else {
         Ok(())
     }
 }
 
-impl<PINS> Write for I2c<I2C1, PINS> {
-    type Error = Error;
+macro_rules! hal {
+    ($($I2CX:ident: ($i2cX:ident, $APB:ident, $i2cXclk:ident),)+) => {
+        $(
+            impl<PINS> I2c<$I2CX, PINS> {
+                /// Configures the I2C peripheral as a master
+                ///
+                /// The timing assumes the longest rise and fall times allowed at `speed`, use
+                /// `set_timing` to adapt it to the actual bus. Transfers are aborted if a slave
+                /// holds SCL low for more than 25 ms or the bus gets stuck, see `set_timeout`.
+                ///
+                /// Fails if no timing for `speed` can be derived from the kernel clock: a kernel
+                /// clock that is too fast runs out of prescaler range at low speeds, one that is too
+                /// slow can't meet the minimum times of the faster modes.
+                pub fn $i2cX(
+                    i2c: $I2CX,
+                    pins: PINS,
+                    speed: KiloHertz,
+                    clocks: Clocks,
+                    apb: &mut $APB,
+                ) -> Result<Self, InvalidConfig>
+                where
+                    PINS: Pins<$I2CX>,
+                {
+                    // Enable clock for I2C
+                    $I2CX::enable(apb);
 
-    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Self::Error> {
-        // Send a START condition
-        self.i2c.cr1.modify(|_, w| w.start().set_bit());
+                    // Reset I2C
+                    $I2CX::reset(apb);
 
-        // Wait until START condition was generated
-        while {
-            let sr1 = self.i2c.sr1.read();
-            sr1.sb().bit_is_clear()
-        } {}
+                    let i2cclk = clocks.$i2cXclk();
+                    let timing = Timing::for_speed(i2cclk, speed.into())?;
 
-        // Also wait until signalled we're master and everything is waiting for us
-        while {
-            let sr2 = self.i2c.sr2.read();
-            sr2.msl().bit_is_clear() && sr2.busy().bit_is_clear()
-        } {}
+                    let mut i2c = I2c {
+                        i2c,
+                        pins,
+                        i2cclk,
+                        sysclk: clocks.sysclk(),
+                        scl_cycles: 0,
+                        timeout: None,
+                    };
+                    i2c.set_timing(timing);
+                    i2c.set_timeout(Some(DEFAULT_TIMEOUT))?;
+                    Ok(i2c)
+                }
 
-        // Set up current address, we're trying to talk to
-        self.i2c
-            .dr
-            .write(|w| unsafe { w.bits(u32::from(addr) << 1) });
+                /// Changes the bus timing
+                pub fn set_timing(&mut self, timing: Timing) {
+                    // The timing can only be changed while the peripheral is disabled
+                    self.i2c.cr1.modify(|_, w| w.pe().clear_bit());
 
-        // Wait until address was sent
-        while {
-            let sr1 = self.i2c.sr1.read();
-            sr1.addr().bit_is_clear()
-        } {}
+                    write_timing(&self.i2c, timing);
+                    self.scl_cycles = (u32::from(timing.scll) + u32::from(timing.sclh) + 2)
+                        * (u32::from(timing.presc) + 1);
 
-        // Clear condition by reading SR2
-        self.i2c.sr2.read();
+                    // Enable the I2C processing
+                    self.i2c.cr1.modify(|_, w| w.pe().set_bit());
+                }
 
-        // Send bytes
-        for c in bytes {
-            self.send_byte(*c)?;
-        }
+                /// Sets how long a slave may hold SCL low, in µs, before the transfer is aborted
+                /// with `Error::Timeout`
+                ///
+                /// `None` disables the timeout. The longest timeout is 8388608 kernel clock cycles.
+                ///
+                /// The timeout only watches SCL, so the blocking transfers also bound their waits
+                /// for the bus in software: a wait gives up with `Error::Timeout` after about twice
+                /// the timeout plus the time of a byte, or after 100000000 SYSCLK cycles if the
+                /// timeout is disabled. This also catches a slave holding SDA low while SCL is
+                /// free. The peripheral is reset then, and `recover_bus` may be needed to free the
+                /// bus.
+                pub fn set_timeout(&mut self, timeout: Option<u32>) -> Result<(), InvalidConfig> {
+                    // TIMEOUTA can only be changed while the timeout is disabled
+                    self.i2c.timeoutr.modify(|_, w| w.timouten().clear_bit());
+                    self.timeout = None;
 
-        // Fallthrough is success
-        Ok(())
-    }
-}
+                    let timeout = match timeout {
+                        Some(timeout) => timeout,
+                        None => return Ok(()),
+                    };
 
-impl<PINS> Read for I2c<I2C1, PINS> {
-    type Error = Error;
-
-    fn read(&mut self, addr: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
-        // Send a START condition and set ACK bit
-        self.i2c
-            .cr1
-            .modify(|_, w| w.start().set_bit().ack().set_bit());
-
-        // Wait until START condition was generated
-        while {
-            let sr1 = self.i2c.sr1.read();
-            sr1.sb().bit_is_clear()
-        } {}
-
-        // Also wait until signalled we're master and everything is waiting for us
-        while {
-            let sr2 = self.i2c.sr2.read();
-            sr2.msl().bit_is_clear() && sr2.busy().bit_is_clear()
-        } {}
-
-        // Set up current address, we're trying to talk to
-        self.i2c
-            .dr
-            .write(|w| unsafe { w.bits((u32::from(addr) << 1) + 1) });
-
-        // Wait until address was sent
-        while {
-            let sr1 = self.i2c.sr1.read();
-            sr1.addr().bit_is_clear()
-        } {}
-
-        // Clear condition by reading SR2
-        self.i2c.sr2.read();
-
-        // Receive bytes into buffer
-        for mut c in buffer {
-            *c = self.recv_byte()?;
-        }
+                    // The timeout counts in steps of 2048 kernel clock cycles
+                    let cycles = u64::from(timeout) * u64::from(self.i2cclk.0);
+                    let steps = (cycles + 2048 * 1_000_000 - 1) / (2048 * 1_000_000);
+                    if steps == 0 || steps > 0x1000 {
+                        return Err(InvalidConfig);
+                    }
 
-        // Send STOP condition
-        self.i2c.cr1.modify(|_, w| w.stop().set_bit());
+                    self.i2c.timeoutr.modify(|_, w| {
+                        w.timeouta()
+                            .bits((steps - 1) as u16)
+                            .tidle()
+                            .clear_bit()
+                            .timouten()
+                            .set_bit()
+                    });
+                    self.timeout = Some(timeout);
 
-        // Fallthrough is success
-        Ok(())
+                    Ok(())
+                }
+
+                pub fn release(self) -> ($I2CX, PINS) {
+                    (self.i2c, self.pins)
+                }
+
+                /// Sends a START condition and the address, followed by the transfer of `len` bytes
+                ///
+                /// With `more`, further bytes follow after a `reload`. Otherwise the transfer ends
+                /// with a STOP condition if `autoend` is set, or else the master holds SCL low so
+                /// the next transfer can begin with a repeated START condition. A `restart` after
+                /// writing to the same slave only repeats the header of a 10-bit address.
+                fn start(
+                    &self,
+                    addr: Address,
+                    read: bool,
+                    len: usize,
+                    more: bool,
+                    autoend: bool,
+                    restart: bool,
+                ) {
+                    let (sadd, ten_bit) = match addr {
+                        Address::SevenBit(addr) => (u16::from(addr) << 1, false),
+                        Address::TenBit(addr) => (addr, true),
+                    };
+
+                    self.i2c.cr2.write(|w| {
+                        w.sadd()
+                            .bits(sadd)
+                            .add10()
+                            .bit(ten_bit)
+                            .head10r()
+                            .bit(ten_bit && restart)
+                            .rd_wrn()
+                            .bit(read)
+                            .nbytes()
+                            .bits(len as u8)
+                            .reload()
+                            .bit(more)
+                            .autoend()
+                            .bit(autoend && !more)
+                            .start()
+                            .set_bit()
+                    });
+                }
+
+                /// Continues the transfer with `len` more bytes
+                fn reload(&self, len: usize, more: bool, autoend: bool) -> Result<(), Error> {
+                    self.wait(|isr| isr.tcr().bit_is_set(), Error::DataNack)?;
+
+                    self.i2c.cr2.modify(|_, w| {
+                        w.nbytes()
+                            .bits(len as u8)
+                            .reload()
+                            .bit(more)
+                            .autoend()
+                            .bit(autoend && !more)
+                    });
+
+                    Ok(())
+                }
+
+                /// Waits for the end of a transfer started by `start`
+                fn end(&self, autoend: bool, nack: Error) -> Result<(), Error> {
+                    if autoend {
+                        self.wait(|isr| isr.stopf().bit_is_set(), nack)?;
+                        self.i2c.icr.write(|w| w.stopcf().set_bit());
+                    } else {
+                        self.wait(|isr| isr.tc().bit_is_set(), nack)?;
+                    }
+
+                    Ok(())
+                }
+
+                /// Waits until `ready` returns `true` for the status flags
+                ///
+                /// A NACK is reported as `nack`, which tells whether the slave rejected its address
+                /// or a data byte.
+                fn wait<F>(&self, ready: F, nack: Error) -> Result<(), Error>
+                where
+                    F: Fn(&i2c1::isr::R) -> bool,
+                {
+                    for _ in 0..self.poll_budget() {
+                        let isr = self.i2c.isr.read();
+
+                        if isr.nackf().bit_is_set() {
+                            self.i2c.icr.write(|w| w.nackcf().set_bit());
+
+                            // A master sends the STOP condition after a NACK on its own, even
+                            // without AUTOEND
+                            self.wait_stop()?;
+
+                            // Drop the byte that wasn't acknowledged
+                            self.i2c.isr.write(|w| w.txe().set_bit());
+
+                            return Err(nack);
+                        }
+
+                        check(&self.i2c, &isr)?;
+                        if ready(&isr) {
+                            return Ok(());
+                        }
+                    }
+
+                    Err(self.abort())
+                }
+
+                /// Waits for the STOP condition after a NACK
+                fn wait_stop(&self) -> Result<(), Error> {
+                    for _ in 0..self.poll_budget() {
+                        let isr = self.i2c.isr.read();
+
+                        check(&self.i2c, &isr)?;
+                        if isr.stopf().bit_is_set() {
+                            self.i2c.icr.write(|w| w.stopcf().set_bit());
+                            return Ok(());
+                        }
+                    }
+
+                    Err(self.abort())
+                }
+
+                /// Returns how often a wait polls the status before giving up on the bus
+                ///
+                /// A poll takes at least one SYSCLK cycle, so the waits last at least twice the
+                /// SCL low timeout, which leaves the hardware to detect a slave stretching the
+                /// clock, plus twice the time of a byte, as rise times and clock stretching slow
+                /// SCL down.
+                fn poll_budget(&self) -> u64 {
+                    let sysclk = u64::from(self.sysclk.0);
+                    let timeout = match self.timeout {
+                        Some(timeout) => 2 * u64::from(timeout) * sysclk / 1_000_000,
+                        None => STUCK_BUS_CYCLES,
+                    };
+
+                    // Nine SCL periods per byte, plus a START or STOP condition
+                    let byte =
+                        2 * 10 * u64::from(self.scl_cycles) * sysclk / u64::from(self.i2cclk.0);
+
+                    timeout + byte
+                }
+
+                /// Resets the peripheral after the bus got stuck, which releases SCL and SDA
+                fn abort(&self) -> Error {
+                    // PE has to stay clear for three APB clock cycles, which reading it back takes
+                    self.i2c.cr1.modify(|_, w| w.pe().clear_bit());
+                    while self.i2c.cr1.read().pe().bit_is_set() {}
+                    self.i2c.cr1.modify(|_, w| w.pe().set_bit());
+
+                    Error::Timeout
+                }
+
+                fn write_bytes<B>(
+                    &mut self,
+                    addr: Address,
+                    bytes: B,
+                    autoend: bool,
+                ) -> Result<(), Error>
+                where
+                    B: IntoIterator<Item = u8>,
+                {
+                    // NBYTES has to be set before the bytes are sent, so they are collected in
+                    // chunks of up to 255 bytes
+                    let mut bytes = bytes.into_iter().peekable();
+                    let mut chunk = [0; 255];
+                    let mut started = false;
+                    let mut sent = false;
+                    let mut more = true;
+
+                    // Every chunk waits for the bus, which gives up if it's stuck, so this only
+                    // keeps going for as long as `bytes` does
+                    while more {
+                        let mut len = 0;
+                        while len < chunk.len() {
+                            match bytes.next() {
+                                Some(byte) => chunk[len] = byte,
+                                None => break,
+                            }
+                            len += 1;
+                        }
+
+                        more = bytes.peek().is_some();
+                        if started {
+                            self.reload(len, more, autoend)?;
+                        } else {
+                            self.start(addr, false, len, more, autoend, false);
+                            started = true;
+                        }
+
+                        for byte in &chunk[..len] {
+                            // Before the first byte, a NACK refers to the address
+                            let nack = if sent {
+                                Error::DataNack
+                            } else {
+                                Error::AddressNack
+                            };
+                            self.wait(|isr| isr.txis().bit_is_set(), nack)?;
+                            self.i2c.txdr.write(|w| w.txdata().bits(*byte));
+                            sent = true;
+                        }
+                    }
+
+                    let nack = if sent {
+                        Error::DataNack
+                    } else {
+                        Error::AddressNack
+                    };
+                    self.end(autoend, nack)
+                }
+
+                fn read_bytes(
+                    &mut self,
+                    addr: Address,
+                    buffer: &mut [u8],
+                    restart: bool,
+                ) -> Result<(), Error> {
+                    let len = buffer.len();
+                    self.start(addr, true, cmp::min(len, 255), len > 255, true, restart);
+
+                    for (i, byte) in buffer.iter_mut().enumerate() {
+                        if i != 0 && i % 255 == 0 {
+                            self.reload(cmp::min(len - i, 255), len - i > 255, true)?;
+                        }
+
+                        // The master acknowledges the data, so only the address can be rejected
+                        self.wait(|isr| isr.rxne().bit_is_set(), Error::AddressNack)?;
+                        *byte = self.i2c.rxdr.read().rxdata().bits();
+                    }
+
+                    self.end(true, Error::AddressNack)
+                }
+
+                /// Writes `bytes`, then reads into `buffer` after a repeated START condition
+                fn write_read_bytes<B>(
+                    &mut self,
+                    addr: Address,
+                    bytes: B,
+                    buffer: &mut [u8],
+                ) -> Result<(), Error>
+                where
+                    B: IntoIterator<Item = u8>,
+                {
+                    self.write_bytes(addr, bytes, false)?;
+                    self.read_bytes(addr, buffer, true)
+                }
+            }
+
+            impl<PINS> I2cSlave<$I2CX, PINS> {
+                /// Configures the I2C peripheral as a slave
+                ///
+                /// Fails if no timing for the speed of `config` can be derived from the kernel
+                /// clock, which can be too fast for the low speeds as well as too slow for the
+                /// faster modes, or if an own address doesn't fit in its 7 or 10 bits or more than
+                /// 7 bits of address 2 are masked.
+                pub fn $i2cX(
+                    i2c: $I2CX,
+                    pins: PINS,
+                    config: SlaveConfig,
+                    clocks: Clocks,
+                    apb: &mut $APB,
+                ) -> Result<Self, InvalidConfig>
+                where
+                    PINS: Pins<$I2CX>,
+                {
+                    // Enable clock for I2C
+                    $I2CX::enable(apb);
+
+                    // Reset I2C
+                    $I2CX::reset(apb);
+
+                    let (oa1, ten_bit) = match config.address1 {
+                        OwnAddress::SevenBit(address) if address <= 0x7f => {
+                            (u16::from(address) << 1, false)
+                        }
+                        OwnAddress::TenBit(address) if address <= 0x3ff => (address, true),
+                        _ => return Err(InvalidConfig),
+                    };
+                    if let Some((address, masked_bits)) = config.address2 {
+                        if address > 0x7f || masked_bits > 7 {
+                            return Err(InvalidConfig);
+                        }
+                    }
+
+                    let timing = Timing::for_speed(clocks.$i2cXclk(), config.speed.into())?;
+                    write_timing(&i2c, timing);
+
+                    i2c.oar1
+                        .write(|w| w.oa1().bits(oa1).oa1mode().bit(ten_bit).oa1en().set_bit());
+
+                    if let Some((address, masked_bits)) = config.address2 {
+                        i2c.oar2.write(|w| {
+                            w.oa2()
+                                .bits(address)
+                                .oa2msk()
+                                .bits(masked_bits)
+                                .oa2en()
+                                .set_bit()
+                        });
+                    }
+
+                    // Enable the I2C processing, with clock stretching
+                    i2c.cr1.modify(|_, w| w.pe().set_bit());
+
+                    Ok(I2cSlave { i2c, pins })
+                }
+
+                /// Starts listening for events, so `poll` can be called from the interrupt handler
+                pub fn listen(&mut self) {
+                    self.i2c.cr1.modify(|_, w| {
+                        w.addrie()
+                            .set_bit()
+                            .rxie()
+                            .set_bit()
+                            .txie()
+                            .set_bit()
+                            .stopie()
+                            .set_bit()
+                            .nackie()
+                            .set_bit()
+                            .errie()
+                            .set_bit()
+                    });
+                }
+
+                /// Stops listening for events
+                pub fn unlisten(&mut self) {
+                    self.i2c.cr1.modify(|_, w| {
+                        w.addrie()
+                            .clear_bit()
+                            .rxie()
+                            .clear_bit()
+                            .txie()
+                            .clear_bit()
+                            .stopie()
+                            .clear_bit()
+                            .nackie()
+                            .clear_bit()
+                            .errie()
+                            .clear_bit()
+                    });
+                }
+
+                /// Returns the next event
+                ///
+                /// A `TransmitRequest` is returned until the byte is passed to `send`.
+                pub fn poll(&mut self) -> nb::Result<SlaveEvent, Error> {
+                    let isr = self.i2c.isr.read();
+                    check(&self.i2c, &isr)?;
+
+                    if isr.nackf().bit_is_set() {
+                        // The master doesn't read any more bytes
+                        self.i2c.icr.write(|w| w.nackcf().set_bit());
+                    }
+
+                    if isr.rxne().bit_is_set() {
+                        Ok(SlaveEvent::Received(self.i2c.rxdr.read().rxdata().bits()))
+                    } else if isr.stopf().bit_is_set() {
+                        self.i2c.icr.write(|w| w.stopcf().set_bit());
+                        Ok(SlaveEvent::Stop)
+                    } else if isr.addr().bit_is_set() {
+                        let address = isr.addcode().bits();
+                        let event = if isr.dir().bit_is_set() {
+                            // Drop the byte the master didn't read in the previous transfer
+                            self.i2c.isr.write(|w| w.txe().set_bit());
+                            SlaveEvent::ReadRequest { address }
+                        } else {
+                            SlaveEvent::WriteRequest { address }
+                        };
+
+                        // Releases SCL
+                        self.i2c.icr.write(|w| w.addrcf().set_bit());
+                        Ok(event)
+                    } else if isr.txis().bit_is_set() {
+                        Ok(SlaveEvent::TransmitRequest)
+                    } else {
+                        Err(nb::Error::WouldBlock)
+                    }
+                }
+
+                /// Sends a byte after a `TransmitRequest`
+                pub fn send(&mut self, byte: u8) {
+                    self.i2c.txdr.write(|w| w.txdata().bits(byte));
+                }
+
+                pub fn release(self) -> ($I2CX, PINS) {
+                    (self.i2c, self.pins)
+                }
+            }
+
+            impl<PINS> Write for I2c<$I2CX, PINS> {
+                type Error = Error;
+
+                fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+                    self.write_bytes(Address::SevenBit(addr), bytes.iter().cloned(), true)
+                }
+            }
+
+            impl<PINS> Write<TenBitAddress> for I2c<$I2CX, PINS> {
+                type Error = Error;
+
+                fn write(&mut self, addr: u16, bytes: &[u8]) -> Result<(), Self::Error> {
+                    self.write_bytes(Address::TenBit(addr), bytes.iter().cloned(), true)
+                }
+            }
+
+            impl<PINS> WriteIter for I2c<$I2CX, PINS> {
+                type Error = Error;
+
+                fn write<B>(&mut self, addr: u8, bytes: B) -> Result<(), Self::Error>
+                where
+                    B: IntoIterator<Item = u8>,
+                {
+                    self.write_bytes(Address::SevenBit(addr), bytes, true)
+                }
+            }
+
+            impl<PINS> WriteIter<TenBitAddress> for I2c<$I2CX, PINS> {
+                type Error = Error;
+
+                fn write<B>(&mut self, addr: u16, bytes: B) -> Result<(), Self::Error>
+                where
+                    B: IntoIterator<Item = u8>,
+                {
+                    self.write_bytes(Address::TenBit(addr), bytes, true)
+                }
+            }
+
+            impl<PINS> Read for I2c<$I2CX, PINS> {
+                type Error = Error;
+
+                fn read(&mut self, addr: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+                    self.read_bytes(Address::SevenBit(addr), buffer, false)
+                }
+            }
+
+            impl<PINS> Read<TenBitAddress> for I2c<$I2CX, PINS> {
+                type Error = Error;
+
+                fn read(&mut self, addr: u16, buffer: &mut [u8]) -> Result<(), Self::Error> {
+                    self.read_bytes(Address::TenBit(addr), buffer, false)
+                }
+            }
+
+            impl<PINS> WriteRead for I2c<$I2CX, PINS> {
+                type Error = Error;
+
+                fn write_read(
+                    &mut self,
+                    addr: u8,
+                    bytes: &[u8],
+                    buffer: &mut [u8],
+                ) -> Result<(), Self::Error> {
+                    self.write_read_bytes(Address::SevenBit(addr), bytes.iter().cloned(), buffer)
+                }
+            }
+
+            impl<PINS> WriteRead<TenBitAddress> for I2c<$I2CX, PINS> {
+                type Error = Error;
+
+                fn write_read(
+                    &mut self,
+                    addr: u16,
+                    bytes: &[u8],
+                    buffer: &mut [u8],
+                ) -> Result<(), Self::Error> {
+                    self.write_read_bytes(Address::TenBit(addr), bytes.iter().cloned(), buffer)
+                }
+            }
+
+            impl<PINS> WriteIterRead for I2c<$I2CX, PINS> {
+                type Error = Error;
+
+                fn write_iter_read<B>(
+                    &mut self,
+                    addr: u8,
+                    bytes: B,
+                    buffer: &mut [u8],
+                ) -> Result<(), Self::Error>
+                where
+                    B: IntoIterator<Item = u8>,
+                {
+                    self.write_read_bytes(Address::SevenBit(addr), bytes, buffer)
+                }
+            }
+
+            impl<PINS> WriteIterRead<TenBitAddress> for I2c<$I2CX, PINS> {
+                type Error = Error;
+
+                fn write_iter_read<B>(
+                    &mut self,
+                    addr: u16,
+                    bytes: B,
+                    buffer: &mut [u8],
+                ) -> Result<(), Self::Error>
+                where
+                    B: IntoIterator<Item = u8>,
+                {
+                    self.write_read_bytes(Address::TenBit(addr), bytes, buffer)
+                }
+            }
+        )+
     }
 }
+
+#[cfg(any(
+    feature = "stm32f7x2",
+    feature = "stm32f7x3",
+    feature = "stm32f7x5",
+    feature = "stm32f7x6",
+    feature = "stm32f7x7",
+    feature = "stm32f7x9",
+))]
+hal! {
+    I2C1: (i2c1, APB1, i2c1clk),
+    I2C2: (i2c2, APB1, i2c2clk),
+    I2C3: (i2c3, APB1, i2c3clk),
+}
+#[cfg(any(
+    feature = "stm32f7x5",
+    feature = "stm32f7x6",
+    feature = "stm32f7x7",
+    feature = "stm32f7x9",
+))]
+hal! {
+    I2C4: (i2c4, APB1, i2c4clk),
+}
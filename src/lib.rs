@@ -12,24 +12,50 @@ pub use nb::block;
 
 pub extern crate stm32f7;
 
-#[cfg(feature = "stm32f7x2")]
+#[cfg(all(feature = "stm32f7x2", not(feature = "mock")))]
 pub use stm32f7::stm32f7x2 as stm32;
 
-#[cfg(feature = "stm32f7x3")]
+#[cfg(all(feature = "stm32f7x3", not(feature = "mock")))]
 pub use stm32f7::stm32f7x3 as stm32;
 
-#[cfg(feature = "stm32f7x5")]
+#[cfg(all(feature = "stm32f7x5", not(feature = "mock")))]
 pub use stm32f7::stm32f7x5 as stm32;
 
-#[cfg(feature = "stm32f7x6")]
+#[cfg(all(feature = "stm32f7x6", not(feature = "mock")))]
 pub use stm32f7::stm32f7x6 as stm32;
 
-#[cfg(feature = "stm32f7x7")]
+#[cfg(all(feature = "stm32f7x7", not(feature = "mock")))]
 pub use stm32f7::stm32f7x7 as stm32;
 
-#[cfg(feature = "stm32f7x9")]
+#[cfg(all(feature = "stm32f7x9", not(feature = "mock")))]
 pub use stm32f7::stm32f7x9 as stm32;
 
+// Simulated peripherals for host tests
+#[cfg(all(
+    feature = "mock",
+    any(
+        feature = "stm32f7x2",
+        feature = "stm32f7x3",
+        feature = "stm32f7x5",
+        feature = "stm32f7x6",
+        feature = "stm32f7x7",
+        feature = "stm32f7x9",
+    )
+))]
+pub mod mock;
+#[cfg(all(
+    feature = "mock",
+    any(
+        feature = "stm32f7x2",
+        feature = "stm32f7x3",
+        feature = "stm32f7x5",
+        feature = "stm32f7x6",
+        feature = "stm32f7x7",
+        feature = "stm32f7x9",
+    )
+))]
+pub use mock as stm32;
+
 // Enable use of interrupt macro
 #[cfg(feature = "rt")]
 pub use stm32f7::interrupt;
@@ -51,6 +77,15 @@ pub mod delay;
     feature = "stm32f7x7",
     feature = "stm32f7x9",
 ))]
+pub mod dma;
+#[cfg(any(
+    feature = "stm32f7x2",
+    feature = "stm32f7x3",
+    feature = "stm32f7x5",
+    feature = "stm32f7x6",
+    feature = "stm32f7x7",
+    feature = "stm32f7x9",
+))]
 pub mod gpio;
 #[cfg(any(
     feature = "stm32f7x2",
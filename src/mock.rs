@@ -0,0 +1,208 @@
+//! Simulated peripherals for running the drivers on the host
+//!
+//! With the `mock` feature enabled, this module takes the place of the device crate re-exported
+//! as `stm32`. It provides everything the device crate does, except that the peripherals used by
+//! this HAL point to zero initialized register blocks in memory instead of the memory mapped
+//! registers. This lets the drivers run unchanged on the host, where tests can inspect the
+//! values they programmed.
+//!
+//! The simulation has no behaviour beyond storing values: flags the hardware would set on its
+//! own are set once by `reset` so drivers waiting for them don't hang. The register blocks are
+//! shared by all threads, so tests have to serialize their accesses.
+
+use core::marker::PhantomData;
+use core::ops::Deref;
+use core::ptr;
+
+#[cfg(feature = "stm32f7x2")]
+use stm32f7::stm32f7x2 as device;
+
+#[cfg(feature = "stm32f7x3")]
+use stm32f7::stm32f7x3 as device;
+
+#[cfg(feature = "stm32f7x5")]
+use stm32f7::stm32f7x5 as device;
+
+#[cfg(feature = "stm32f7x6")]
+use stm32f7::stm32f7x6 as device;
+
+#[cfg(feature = "stm32f7x7")]
+use stm32f7::stm32f7x7 as device;
+
+#[cfg(feature = "stm32f7x9")]
+use stm32f7::stm32f7x9 as device;
+
+// The peripherals defined below shadow the ones of the device crate
+pub use self::device::*;
+
+macro_rules! mock {
+    ($clear:ident: [$($PER:ident,)+]) => {
+        $(
+            /// Simulated peripheral
+            pub struct $PER {
+                _marker: PhantomData<*const ()>,
+            }
+
+            unsafe impl Send for $PER {}
+
+            impl $PER {
+                /// Returns a pointer to the simulated register block
+                pub fn ptr() -> *const <device::$PER as Deref>::Target {
+                    $PER::memory().0 as *const _
+                }
+
+                /// Creates an instance of the peripheral
+                ///
+                /// # Safety
+                ///
+                /// Unlike on the hardware there is no `Peripherals::take` to hand out the
+                /// simulated peripherals, so it's up to the caller not to create aliases.
+                pub unsafe fn steal() -> Self {
+                    $PER {
+                        _marker: PhantomData,
+                    }
+                }
+
+                fn memory() -> (*mut u32, usize) {
+                    const WORDS: usize =
+                        ::core::mem::size_of::<<device::$PER as Deref>::Target>() / 4;
+                    static mut MEMORY: [u32; WORDS] = [0; WORDS];
+
+                    (ptr::addr_of_mut!(MEMORY) as *mut u32, WORDS)
+                }
+            }
+
+            impl Deref for $PER {
+                type Target = <device::$PER as Deref>::Target;
+
+                fn deref(&self) -> &Self::Target {
+                    unsafe { &*$PER::ptr() }
+                }
+            }
+        )+
+
+        unsafe fn $clear() {
+            $(
+                let (memory, words) = $PER::memory();
+                ptr::write_bytes(memory, 0, words);
+            )+
+        }
+    }
+}
+
+mock! {
+    clear: [
+    DMA1,
+    DMA2,
+    FLASH,
+    GPIOA,
+    GPIOB,
+    GPIOC,
+    GPIOD,
+    GPIOE,
+    GPIOF,
+    GPIOG,
+    GPIOH,
+    GPIOI,
+    I2C1,
+    I2C2,
+    I2C3,
+    LPTIM1,
+    PWR,
+    RCC,
+    RNG,
+    SPI1,
+    SPI2,
+    SPI3,
+    SPI4,
+    SPI5,
+    SYSCFG,
+    TIM1,
+    TIM2,
+    TIM3,
+    TIM4,
+    TIM5,
+    TIM6,
+    TIM7,
+    TIM8,
+    TIM9,
+    TIM10,
+    TIM11,
+    TIM12,
+    TIM13,
+    TIM14,
+    UART4,
+    UART5,
+    UART7,
+    UART8,
+    USART1,
+    USART2,
+    USART3,
+    USART6,
+    ]
+}
+
+#[cfg(not(any(feature = "stm32f7x2", feature = "stm32f7x3")))]
+mock! {
+    clear_large_packages: [
+    GPIOJ,
+    GPIOK,
+    I2C4,
+    SPI6,
+    ]
+}
+
+/// Clears all simulated registers and sets the flags the hardware sets on its own
+///
+/// Oscillators, PLLs and the voltage regulator report ready, and the U(S)ARTs and SPIs report an
+/// empty transmit buffer.
+///
+/// # Safety
+///
+/// Must not be called while any driver is in use.
+pub unsafe fn reset() {
+    clear();
+    #[cfg(not(any(feature = "stm32f7x2", feature = "stm32f7x3")))]
+    clear_large_packages();
+
+    let rcc = &*RCC::ptr();
+    // HSIRDY, HSERDY, PLLRDY, PLLI2SRDY and PLLSAIRDY
+    set_bits(
+        &rcc.cr,
+        (1 << 1) | (1 << 17) | (1 << 25) | (1 << 27) | (1 << 29),
+    );
+    // LSERDY
+    set_bits(&rcc.bdcr, 1 << 1);
+    // LSIRDY
+    set_bits(&rcc.csr, 1 << 1);
+
+    // VOSRDY, ODRDY and ODSWRDY
+    set_bits(&(*PWR::ptr()).csr1, (1 << 14) | (1 << 16) | (1 << 17));
+
+    // TC and TXE
+    set_bits(&(*USART1::ptr()).isr, (1 << 6) | (1 << 7));
+    set_bits(&(*USART2::ptr()).isr, (1 << 6) | (1 << 7));
+    set_bits(&(*USART3::ptr()).isr, (1 << 6) | (1 << 7));
+    set_bits(&(*UART4::ptr()).isr, (1 << 6) | (1 << 7));
+    set_bits(&(*UART5::ptr()).isr, (1 << 6) | (1 << 7));
+    set_bits(&(*USART6::ptr()).isr, (1 << 6) | (1 << 7));
+    set_bits(&(*UART7::ptr()).isr, (1 << 6) | (1 << 7));
+    set_bits(&(*UART8::ptr()).isr, (1 << 6) | (1 << 7));
+
+    // TXE
+    set_bits(&(*SPI1::ptr()).sr, 1 << 1);
+    set_bits(&(*SPI2::ptr()).sr, 1 << 1);
+    set_bits(&(*SPI3::ptr()).sr, 1 << 1);
+    set_bits(&(*SPI4::ptr()).sr, 1 << 1);
+    set_bits(&(*SPI5::ptr()).sr, 1 << 1);
+    #[cfg(not(any(feature = "stm32f7x2", feature = "stm32f7x3")))]
+    set_bits(&(*SPI6::ptr()).sr, 1 << 1);
+}
+
+/// Sets bits of a register, bypassing the access restrictions of the device crate
+///
+/// The registers consist of `VolatileCell`s, so writing through a shared reference is fine.
+unsafe fn set_bits<REG>(register: *const REG, bits: u32) {
+    let register = register as *mut u32;
+    ptr::write_volatile(register, ptr::read_volatile(register) | bits);
+}
@@ -1,5 +1,6 @@
 pub use hal::prelude::*;
 
+pub use dma::DmaExt as _stm32f7xx_hal_dma_DmaExt;
 pub use gpio::GpioExt as _stm32f7xx_hal_gpio_GpioExt;
 pub use i2c::Pins as _stm32f7xx_hal_i2c_Pins;
 pub use rcc::RccExt as _stm32f7xx_hal_rcc_RccExt;
@@ -1,7 +1,15 @@
-use core::cmp::min;
-
-use stm32::{FLASH, RCC};
+use stm32::rcc::{self, RegisterBlock};
+use stm32::{DMA1, DMA2, FLASH, PWR, RCC, RNG, SYSCFG};
+use stm32::{GPIOA, GPIOB, GPIOC, GPIOD, GPIOE, GPIOF, GPIOG, GPIOH, GPIOI};
+#[cfg(not(any(feature = "stm32f7x2", feature = "stm32f7x3")))]
+use stm32::{GPIOJ, GPIOK, I2C4, SPI6};
+use stm32::{I2C1, I2C2, I2C3, LPTIM1, SPI1, SPI2, SPI3, SPI4, SPI5};
+use stm32::{TIM1, TIM10, TIM11, TIM12, TIM13, TIM14, TIM2, TIM3, TIM4, TIM5, TIM6, TIM7};
+use stm32::{TIM8, TIM9, UART4, UART5, UART7, UART8, USART1, USART2, USART3, USART6};
 
+use gpio::gpioa::PA8;
+use gpio::gpioc::PC9;
+use gpio::{Alternate, AF0};
 use time::Hertz;
 
 /// Extension trait that constrains the `RCC` peripheral
@@ -13,7 +21,23 @@ pub trait RccExt {
 impl RccExt for RCC {
     fn constrain(self) -> Rcc {
         Rcc {
+            ahb1: AHB1 { _0: () },
+            ahb2: AHB2 { _0: () },
+            apb1: APB1 { _0: () },
+            apb2: APB2 { _0: () },
             cfgr: CFGR {
+                hse: None,
+                hse_bypass: false,
+                css: false,
+                pll48clk: false,
+                plli2s: None,
+                plli2s_divq: 1,
+                pllsai: None,
+                pllsai_divq: 1,
+                pllsai_divr: 2,
+                usart_clocks: [UsartClock::Pclk; 8],
+                i2c_clocks: [I2cClock::Pclk; 4],
+                lptim1_clock: LptimClock::Pclk,
                 hclk: None,
                 pclk1: None,
                 pclk2: None,
@@ -25,12 +49,219 @@ impl RccExt for RCC {
 
 /// Constrained RCC peripheral
 pub struct Rcc {
+    /// Advanced High-performance Bus 1 (AHB1) registers
+    pub ahb1: AHB1,
+    /// Advanced High-performance Bus 2 (AHB2) registers
+    pub ahb2: AHB2,
+    /// Advanced Peripheral Bus 1 (APB1) registers
+    pub apb1: APB1,
+    /// Advanced Peripheral Bus 2 (APB2) registers
+    pub apb2: APB2,
     pub cfgr: CFGR,
 }
 
+macro_rules! bus {
+    ($($BUS:ident: ($ENR:ident, $enr:ident, $RSTR:ident, $rstr:ident, $doc:expr),)+) => {
+        $(
+            #[doc = $doc]
+            pub struct $BUS {
+                _0: (),
+            }
+
+            impl $BUS {
+                fn enr(&mut self) -> &rcc::$ENR {
+                    // NOTE(unsafe) this proxy grants exclusive access to this register
+                    unsafe { &(*RCC::ptr()).$enr }
+                }
+
+                fn rstr(&mut self) -> &rcc::$RSTR {
+                    // NOTE(unsafe) this proxy grants exclusive access to this register
+                    unsafe { &(*RCC::ptr()).$rstr }
+                }
+            }
+        )+
+    }
+}
+
+bus! {
+    AHB1: (AHB1ENR, ahb1enr, AHB1RSTR, ahb1rstr, "Advanced High-performance Bus 1 (AHB1) registers"),
+    AHB2: (AHB2ENR, ahb2enr, AHB2RSTR, ahb2rstr, "Advanced High-performance Bus 2 (AHB2) registers"),
+    APB1: (APB1ENR, apb1enr, APB1RSTR, apb1rstr, "Advanced Peripheral Bus 1 (APB1) registers"),
+    APB2: (APB2ENR, apb2enr, APB2RSTR, apb2rstr, "Advanced Peripheral Bus 2 (APB2) registers"),
+}
+
+/// Bus a peripheral is connected to
+pub trait RccBus {
+    /// The bus proxy granting access to the enable and reset registers of the peripheral
+    type Bus;
+}
+
+/// Enable and disable the clock of a peripheral
+pub trait Enable: RccBus {
+    /// Enables the clock of the peripheral
+    fn enable(bus: &mut Self::Bus);
+
+    /// Disables the clock of the peripheral
+    fn disable(bus: &mut Self::Bus);
+}
+
+/// Reset a peripheral
+pub trait Reset: RccBus {
+    /// Resets the peripheral to a clean slate state
+    fn reset(bus: &mut Self::Bus);
+}
+
+macro_rules! peripherals {
+    ($($PER:ident: ($BUS:ident, $bit:expr),)+) => {
+        $(
+            impl RccBus for $PER {
+                type Bus = $BUS;
+            }
+
+            impl Enable for $PER {
+                fn enable(bus: &mut Self::Bus) {
+                    bus.enr().modify(|r, w| unsafe { w.bits(r.bits() | (1 << $bit)) });
+                }
+
+                fn disable(bus: &mut Self::Bus) {
+                    bus.enr().modify(|r, w| unsafe { w.bits(r.bits() & !(1 << $bit)) });
+                }
+            }
+
+            impl Reset for $PER {
+                fn reset(bus: &mut Self::Bus) {
+                    bus.rstr().modify(|r, w| unsafe { w.bits(r.bits() | (1 << $bit)) });
+                    bus.rstr().modify(|r, w| unsafe { w.bits(r.bits() & !(1 << $bit)) });
+                }
+            }
+        )+
+    }
+}
+
+// The enable and reset bits share the same position, so they're given by index as their names
+// aren't consistent between the device register definitions
+peripherals! {
+    GPIOA: (AHB1, 0),
+    GPIOB: (AHB1, 1),
+    GPIOC: (AHB1, 2),
+    GPIOD: (AHB1, 3),
+    GPIOE: (AHB1, 4),
+    GPIOF: (AHB1, 5),
+    GPIOG: (AHB1, 6),
+    GPIOH: (AHB1, 7),
+    GPIOI: (AHB1, 8),
+    DMA1: (AHB1, 21),
+    DMA2: (AHB1, 22),
+
+    RNG: (AHB2, 6),
+
+    TIM2: (APB1, 0),
+    TIM3: (APB1, 1),
+    TIM4: (APB1, 2),
+    TIM5: (APB1, 3),
+    TIM6: (APB1, 4),
+    TIM7: (APB1, 5),
+    TIM12: (APB1, 6),
+    TIM13: (APB1, 7),
+    TIM14: (APB1, 8),
+    LPTIM1: (APB1, 9),
+    SPI2: (APB1, 14),
+    SPI3: (APB1, 15),
+    USART2: (APB1, 17),
+    USART3: (APB1, 18),
+    UART4: (APB1, 19),
+    UART5: (APB1, 20),
+    I2C1: (APB1, 21),
+    I2C2: (APB1, 22),
+    I2C3: (APB1, 23),
+    PWR: (APB1, 28),
+    UART7: (APB1, 30),
+    UART8: (APB1, 31),
+
+    TIM1: (APB2, 0),
+    TIM8: (APB2, 1),
+    USART1: (APB2, 4),
+    USART6: (APB2, 5),
+    SPI1: (APB2, 12),
+    SPI4: (APB2, 13),
+    SYSCFG: (APB2, 14),
+    TIM9: (APB2, 16),
+    TIM10: (APB2, 17),
+    TIM11: (APB2, 18),
+    SPI5: (APB2, 20),
+}
+
+#[cfg(not(any(feature = "stm32f7x2", feature = "stm32f7x3")))]
+peripherals! {
+    GPIOJ: (AHB1, 9),
+    GPIOK: (AHB1, 10),
+    I2C4: (APB1, 24),
+    SPI6: (APB2, 21),
+}
+
+/// Generates `CFGR` methods selecting the kernel clock of a peripheral
+macro_rules! kernel_clocks {
+    ($($method:ident: ($field:ident[$i:expr], $Source:ident, $doc:expr),)+) => {
+        $(
+            #[doc = $doc]
+            pub fn $method(mut self, source: $Source) -> Self {
+                self.$field[$i] = source;
+                self
+            }
+        )+
+    }
+}
+
+/// Generates `Clocks` accessors for the kernel clock of a peripheral
+macro_rules! kernel_clock_accessors {
+    ($($method:ident: ($field:ident[$i:expr], $doc:expr),)+) => {
+        $(
+            #[doc = $doc]
+            pub fn $method(&self) -> Hertz {
+                self.$field[$i]
+            }
+        )+
+    }
+}
+
 const HSI: u32 = 16_000_000; // Hz
+const LSE: u32 = 32_768; // Hz
+const LSI: u32 = 32_000; // Hz
+
+const PLL48CLK: u32 = 48_000_000; // Hz
+
+const HSE_MIN: u32 = 4_000_000; // Hz
+const HSE_MAX: u32 = 26_000_000; // Hz
+
+const SYSCLK_MAX: u32 = 216_000_000; // Hz
+const PCLK1_MAX: u32 = 54_000_000; // Hz
+const PCLK2_MAX: u32 = 108_000_000; // Hz
+
+// Frequency limits without over-drive
+const SYSCLK_MAX_NO_OVERDRIVE: u32 = 180_000_000; // Hz
+const PCLK1_MAX_NO_OVERDRIVE: u32 = 45_000_000; // Hz
+const PCLK2_MAX_NO_OVERDRIVE: u32 = 90_000_000; // Hz
+
+// Maximum SYSCLK for the lower power voltage scales
+const SYSCLK_MAX_SCALE3: u32 = 144_000_000; // Hz
+const SYSCLK_MAX_SCALE2: u32 = 168_000_000; // Hz
+
+// Additional flash wait state per 30 MHz of SYSCLK at 2.7 to 3.6 V
+const FLASH_LATENCY_STEP: u32 = 30_000_000; // Hz
 
 pub struct CFGR {
+    hse: Option<u32>,
+    hse_bypass: bool,
+    css: bool,
+    pll48clk: bool,
+    plli2s: Option<AuxPllConfig>,
+    plli2s_divq: u8,
+    pllsai: Option<AuxPllConfig>,
+    pllsai_divq: u8,
+    pllsai_divr: u8,
+    usart_clocks: [UsartClock; 8],
+    i2c_clocks: [I2cClock; 4],
+    lptim1_clock: LptimClock,
     hclk: Option<u32>,
     pclk1: Option<u32>,
     pclk2: Option<u32>,
@@ -38,6 +269,109 @@ pub struct CFGR {
 }
 
 impl CFGR {
+    /// Uses HSE (external oscillator) instead of HSI (internal RC oscillator) as the clock source.
+    /// Will result in a hang if an external oscillator is not connected or it fails to start.
+    pub fn use_hse<F>(mut self, freq: F) -> Self
+    where
+        F: Into<Hertz>,
+    {
+        self.hse = Some(freq.into().0);
+        self
+    }
+
+    /// Bypasses the HSE oscillator so an external clock signal can be fed directly into OSC_IN.
+    /// Only has an effect together with `use_hse`.
+    pub fn bypass_hse_oscillator(mut self) -> Self {
+        self.hse_bypass = true;
+        self
+    }
+
+    /// Enables the clock security system, which switches SYSCLK to the HSI and raises an NMI if
+    /// the HSE fails. Only has an effect together with `use_hse`; see `handle_css_failure`.
+    pub fn enable_css(mut self) -> Self {
+        self.css = true;
+        self
+    }
+
+    /// Requires the PLL48CLK domain, which clocks USB OTG, SDMMC and RNG, to run at exactly
+    /// 48 MHz. This restricts the achievable SYSCLK frequencies and keeps the PLL running even if
+    /// SYSCLK is taken directly from an oscillator.
+    pub fn require_pll48clk(mut self) -> Self {
+        self.pll48clk = true;
+        self
+    }
+
+    /// Enables PLLI2S with the multiplication factor `n` and the division factors `p`, `q` and
+    /// `r` for the SPDIFRX, SAI and I2S clocks.
+    ///
+    /// PLLI2S shares its input divider with the main PLL. `n` must be in the range from 50 to 432,
+    /// `p` one of 2, 4, 6 or 8, `q` in the range from 2 to 15 and `r` in the range from 2 to 7.
+    /// The devices without SPDIFRX have no P output and ignore `p`.
+    pub fn plli2s(mut self, n: u16, p: u8, q: u8, r: u8) -> Self {
+        self.plli2s = Some(AuxPllConfig {
+            n,
+            p: if SPDIFRX_LTDC_OUTPUTS { Some(p) } else { None },
+            q,
+            r: Some(r),
+        });
+        self
+    }
+
+    /// Sets the division factor from PLLI2SQ to the SAI clock, in the range from 1 to 32
+    pub fn plli2s_divq(mut self, div: u8) -> Self {
+        self.plli2s_divq = div;
+        self
+    }
+
+    /// Enables PLLSAI with the multiplication factor `n` and the division factors `p`, `q` and
+    /// `r` for the alternate 48 MHz, SAI and LTDC clocks.
+    ///
+    /// PLLSAI shares its input divider with the main PLL. `n` must be in the range from 50 to 432,
+    /// `p` one of 2, 4, 6 or 8, `q` in the range from 2 to 15 and `r` in the range from 2 to 7.
+    /// The devices without LTDC have no R output and ignore `r` and `pllsai_divr`.
+    pub fn pllsai(mut self, n: u16, p: u8, q: u8, r: u8) -> Self {
+        self.pllsai = Some(AuxPllConfig {
+            n,
+            p: Some(p),
+            q,
+            r: if SPDIFRX_LTDC_OUTPUTS { Some(r) } else { None },
+        });
+        self
+    }
+
+    /// Sets the division factor from PLLSAIQ to the SAI clock, in the range from 1 to 32
+    pub fn pllsai_divq(mut self, div: u8) -> Self {
+        self.pllsai_divq = div;
+        self
+    }
+
+    /// Sets the division factor from PLLSAIR to the LTDC clock, one of 2, 4, 8 or 16
+    pub fn pllsai_divr(mut self, div: u8) -> Self {
+        self.pllsai_divr = div;
+        self
+    }
+
+    kernel_clocks! {
+        usart1_clock: (usart_clocks[0], UsartClock, "Selects the kernel clock of USART1"),
+        usart2_clock: (usart_clocks[1], UsartClock, "Selects the kernel clock of USART2"),
+        usart3_clock: (usart_clocks[2], UsartClock, "Selects the kernel clock of USART3"),
+        uart4_clock: (usart_clocks[3], UsartClock, "Selects the kernel clock of UART4"),
+        uart5_clock: (usart_clocks[4], UsartClock, "Selects the kernel clock of UART5"),
+        usart6_clock: (usart_clocks[5], UsartClock, "Selects the kernel clock of USART6"),
+        uart7_clock: (usart_clocks[6], UsartClock, "Selects the kernel clock of UART7"),
+        uart8_clock: (usart_clocks[7], UsartClock, "Selects the kernel clock of UART8"),
+        i2c1_clock: (i2c_clocks[0], I2cClock, "Selects the kernel clock of I2C1"),
+        i2c2_clock: (i2c_clocks[1], I2cClock, "Selects the kernel clock of I2C2"),
+        i2c3_clock: (i2c_clocks[2], I2cClock, "Selects the kernel clock of I2C3"),
+        i2c4_clock: (i2c_clocks[3], I2cClock, "Selects the kernel clock of I2C4"),
+    }
+
+    /// Selects the kernel clock of LPTIM1
+    pub fn lptim1_clock(mut self, source: LptimClock) -> Self {
+        self.lptim1_clock = source;
+        self
+    }
+
     pub fn hclk<F>(mut self, freq: F) -> Self
     where
         F: Into<Hertz>,
@@ -70,173 +404,719 @@ impl CFGR {
         self
     }
 
-    pub fn freeze(self) -> Clocks {
+    /// Calculates the register settings for the requested clock configuration
+    ///
+    /// This is pure logic and does not touch any hardware
+    fn setup(&self) -> Result<ClockSetup, ClockError> {
+        let base_clk = match self.hse {
+            Some(hse) if !(HSE_MIN..=HSE_MAX).contains(&hse) => return Err(ClockError::InvalidHse),
+            Some(hse) => hse,
+            None => HSI,
+        };
+
+        let sysclk = self.sysclk.unwrap_or(base_clk);
+
+        // Only use the PLL for SYSCLK if the oscillator can't be used directly
+        let use_pll = sysclk != base_clk;
+
+        // Bring up the PLL if it is needed for SYSCLK or the 48 MHz domain
+        let pll = if use_pll || self.pll48clk {
+            let sysclk = if use_pll { Some(sysclk) } else { None };
+            Some(PllConfig::solve(
+                base_clk,
+                sysclk,
+                self.pll48clk,
+                SYSCLK_MAX,
+            )?)
+        } else {
+            None
+        };
+
+        let (hpre, hpre_bits) = select_divider(sysclk, self.hclk, SYSCLK_MAX, &HPRE_DIVIDERS)
+            .map_err(ClockError::Hclk)?;
+        let hclk = sysclk / hpre;
+
+        // Over-drive is needed above 180 MHz and raises the bus frequency limits as well
+        let overdrive = sysclk > SYSCLK_MAX_NO_OVERDRIVE;
+        let (pclk1_max, pclk2_max) = if overdrive {
+            (PCLK1_MAX, PCLK2_MAX)
+        } else {
+            (PCLK1_MAX_NO_OVERDRIVE, PCLK2_MAX_NO_OVERDRIVE)
+        };
+
+        // Use the lowest voltage scale supporting the requested frequency
+        let vos = if sysclk <= SYSCLK_MAX_SCALE3 {
+            VoltageScale::Scale3
+        } else if sysclk <= SYSCLK_MAX_SCALE2 {
+            VoltageScale::Scale2
+        } else {
+            VoltageScale::Scale1
+        };
+
+        // The auxiliary PLLs share the input divider of the main PLL
+        let pllm = pll.map_or_else(|| aux_pllm(base_clk), |pll| u32::from(pll.m));
+        let aux_vco = |n: u16| u64::from(base_clk) * u64::from(n) / u64::from(pllm);
+
+        if let Some(plli2s) = self.plli2s {
+            if !plli2s.is_valid(aux_vco(plli2s.n)) || self.plli2s_divq < 1 || self.plli2s_divq > 32
+            {
+                return Err(ClockError::Plli2s);
+            }
+        }
+
+        if let Some(pllsai) = self.pllsai {
+            let divr_valid = pllsai.r.is_none() || matches!(self.pllsai_divr, 2 | 4 | 8 | 16);
+            if !pllsai.is_valid(aux_vco(pllsai.n))
+                || self.pllsai_divq < 1
+                || self.pllsai_divq > 32
+                || !divr_valid
+            {
+                return Err(ClockError::Pllsai);
+            }
+        }
+
+        let plli2s = self.plli2s.map(|plli2s| plli2s.outputs(aux_vco(plli2s.n)));
+        let pllsai = self.pllsai.map(|pllsai| pllsai.outputs(aux_vco(pllsai.n)));
+
+        let (ppre1, ppre1_bits) = select_divider(hclk, self.pclk1, pclk1_max, &PPRE_DIVIDERS)
+            .map_err(ClockError::Pclk1)?;
+        let (ppre2, ppre2_bits) = select_divider(hclk, self.pclk2, pclk2_max, &PPRE_DIVIDERS)
+            .map_err(ClockError::Pclk2)?;
+
+        let sysclk = Hertz(sysclk);
+        let pclk1 = Hertz(hclk / ppre1);
+        let pclk2 = Hertz(hclk / ppre2);
+
+        // UxSEL fields are 2 bits wide, starting with USART1 at bit 0, followed by the I2CxSEL
+        // fields at bit 16 and LPTIM1SEL at bit 24
+        let mut kernel_clock_bits = (self.lptim1_clock as u32) << 24;
+        for (i, &source) in self.usart_clocks.iter().enumerate() {
+            kernel_clock_bits |= (source as u32) << (2 * i);
+        }
+        for (i, &source) in self.i2c_clocks.iter().enumerate() {
+            kernel_clock_bits |= (source as u32) << (16 + 2 * i);
+        }
+
+        let lse =
+            self.usart_clocks.contains(&UsartClock::Lse) || self.lptim1_clock == LptimClock::Lse;
+        let lsi = self.lptim1_clock == LptimClock::Lsi;
+
+        let (usart_clks, i2c_clks, lptim1clk) =
+            kernel_clocks(kernel_clock_bits, sysclk, pclk1, pclk2);
+
+        Ok(ClockSetup {
+            pll,
+            use_pll,
+            pllm: pllm as u8,
+            vos,
+            overdrive,
+            hpre_bits,
+            ppre1_bits,
+            ppre2_bits,
+            lse,
+            lsi,
+            kernel_clock_bits,
+            clocks: Clocks {
+                hse: self.hse.map(Hertz),
+                hclk: Hertz(hclk),
+                pclk1,
+                pclk2,
+                ppre1: ppre1 as u8,
+                ppre2: ppre2 as u8,
+                sysclk,
+                pll48clk: pll.map(|pll| pll.pll48clk(base_clk)),
+                plli2s,
+                plli2s_divq: plli2s.map(|(_, q, _)| Hertz(q.0 / u32::from(self.plli2s_divq))),
+                pllsai,
+                pllsai_divq: pllsai.map(|(_, q, _)| Hertz(q.0 / u32::from(self.pllsai_divq))),
+                pllsai_divr: pllsai
+                    .and_then(|(_, _, r)| r)
+                    .map(|r| Hertz(r.0 / u32::from(self.pllsai_divr))),
+                usart_clks,
+                i2c_clks,
+                lptim1clk,
+            },
+        })
+    }
+
+    /// Freezes the clock configuration, making it effective
+    ///
+    /// Returns an error describing the closest achievable frequency if one of the requested
+    /// clocks can't be generated exactly.
+    pub fn freeze(self) -> Result<Clocks, ClockError> {
+        let setup = self.setup()?;
+
         let flash = unsafe { &(*FLASH::ptr()) };
+        let pwr = unsafe { &*PWR::ptr() };
         let rcc = unsafe { &*RCC::ptr() };
 
-        let sysclk = self.sysclk.unwrap_or(HSI);
-        let mut hclk = self.hclk.unwrap_or(sysclk);
-
-        assert!(hclk <= sysclk);
-
-        if sysclk == HSI {
-            let hpre_bits = match sysclk / hclk {
-                0 => unreachable!(),
-                1 => 0b0000,
-                2 => 0b1000,
-                3...5 => 0b1001,
-                6...11 => 0b1010,
-                12...39 => 0b1011,
-                40...95 => 0b1100,
-                96...191 => 0b1101,
-                192...383 => 0b1110,
-                _ => 0b1111,
-            };
+        // Run from HSI while reconfiguring, a bootloader may have left the system clock on HSE
+        // or the PLL, which then can't be stopped
+        rcc.cr.modify(|_, w| w.hsion().set_bit());
+        while rcc.cr.read().hsirdy().bit_is_clear() {}
+        rcc.cfgr.modify(|_, w| w.sw().hsi());
+        while !rcc.cfgr.read().sws().is_hsi() {}
 
-            // Use HSI as source and run everything at the same speed
-            rcc.cfgr.modify(|_, w| unsafe {
-                w.ppre2()
-                    .bits(0)
-                    .ppre1()
-                    .bits(0)
-                    .hpre()
-                    .bits(hpre_bits)
-                    .sw()
-                    .hsi()
+        // The PLLs ignore changes to their configuration while they are running
+        let cr = rcc.cr.read();
+        if cr.pllon().bit_is_set() || cr.plli2son().bit_is_set() || cr.pllsaion().bit_is_set() {
+            rcc.cr.modify(|_, w| {
+                w.pllon()
+                    .clear_bit()
+                    .plli2son()
+                    .clear_bit()
+                    .pllsaion()
+                    .clear_bit()
             });
+            loop {
+                let cr = rcc.cr.read();
+                if cr.pllrdy().bit_is_clear()
+                    && cr.plli2srdy().bit_is_clear()
+                    && cr.pllsairdy().bit_is_clear()
+                {
+                    break;
+                }
+            }
+        }
 
-            Clocks {
-                hclk: Hertz(hclk),
-                pclk1: Hertz(hclk),
-                pclk2: Hertz(hclk),
-                ppre1: 1,
-                ppre2: 1,
-                sysclk: Hertz(sysclk),
+        // The voltage scale can only be changed while the PLL is off and becomes effective once
+        // the PLL is running
+        rcc.apb1enr.modify(|_, w| w.pwren().set_bit());
+        pwr.cr1.modify(|_, w| match setup.vos {
+            VoltageScale::Scale1 => w.vos().scale1(),
+            VoltageScale::Scale2 => w.vos().scale2(),
+            VoltageScale::Scale3 => w.vos().scale3(),
+        });
+
+        if self.hse.is_some() {
+            // Bypass can only be changed while the oscillator is disabled
+            let cr = rcc.cr.read();
+            if cr.hseon().bit_is_set() && cr.hsebyp().bit() != self.hse_bypass {
+                rcc.cr.modify(|_, w| w.hseon().clear_bit());
+                while rcc.cr.read().hserdy().bit_is_set() {}
             }
-        } else {
-            #[cfg(any(
-                feature = "stm32f7x2",
-                feature = "stm32f7x3",
-                feature = "stm32f7x5",
-                feature = "stm32f7x6",
-                feature = "stm32f7x7",
-                feature = "stm32f7x9",
-            ))]
-            let sysclk_min = 24_000_000;
-
-            #[cfg(any(
-                feature = "stm32f7x2",
-                feature = "stm32f7x3",
-                feature = "stm32f7x5",
-                feature = "stm32f7x6",
-                feature = "stm32f7x7",
-                feature = "stm32f7x9",
-            ))]
-            let sysclk_max = 180_000_000;
-
-            assert!(sysclk <= sysclk_max && sysclk >= sysclk_min);
-
-            // We're not diving down the hclk so it'll be the same as sysclk
-            hclk = sysclk;
-
-            // Sysclk output divisor must be one of 2, 4, 6 or 8
-            let sysclk_div = min(8, (432_000_000 / sysclk) & !1);
-
-            // Input divisor from HSI clock, must result to frequency in
-            // the range from 1 to 2 MHz
-            let pllm = 16;
-
-            // Main scaler, must result in >= 100MHz (>= 192MHz for F401)
-            // and <= 432MHz, min 50, max 432
-            let plln = sysclk * sysclk_div / (HSI / pllm as u32);
-
-            let pllp = (sysclk_div as u8 / 2) - 1;
-
-            let (ppre1_bits, ppre2_bits) = match hclk {
-                45_000_001...90_000_000 => (0b100, 0b011),
-                90_000_001...180_000_000 => (0b101, 0b100),
-                _ => (0b011, 0b011),
-            };
+            rcc.cr.modify(|_, w| w.hsebyp().bit(self.hse_bypass));
+            rcc.cr.modify(|_, w| w.hseon().set_bit());
+
+            // Wait for HSE to stabilise
+            while rcc.cr.read().hserdy().bit_is_clear() {}
 
-            // Calculate real divisor
-            let ppre1 = 1 << (ppre1_bits - 0b011);
-            let ppre2 = 1 << (ppre2_bits - 0b011);
-
-            // Calculate new bus clocks
-            let pclk1 = hclk / ppre1 as u32;
-            let pclk2 = hclk / ppre2 as u32;
-
-            #[cfg(any(
-                feature = "stm32f7x2",
-                feature = "stm32f7x3",
-                feature = "stm32f7x5",
-                feature = "stm32f7x6",
-                feature = "stm32f7x7",
-                feature = "stm32f7x9",
-            ))]
-            let flash_latency_step = 30_000_000;
-
-            // Adjust flash wait states
-            unsafe {
-                flash
-                    .acr
-                    .modify(|_, w| w.latency().bits(((sysclk - 1) / flash_latency_step) as u8))
+            if self.css {
+                rcc.cr.modify(|_, w| w.csson().set_bit());
             }
+        }
 
-            // use PLL as source
+        if let Some(pll) = setup.pll {
             rcc.pllcfgr.write(|w| unsafe {
                 w.pllm()
-                    .bits(pllm)
+                    .bits(pll.m)
                     .plln()
-                    .bits(plln as u16)
+                    .bits(pll.n)
                     .pllp()
-                    .bits(pllp)
+                    .bits(pll.p / 2 - 1)
+                    .pllq()
+                    .bits(pll.q)
+                    .pllsrc()
+                    .bit(self.hse.is_some())
             });
 
-            // Enable PLL
-            rcc.cr.write(|w| w.pllon().set_bit());
+            // Enable PLL, keeping the oscillators running
+            rcc.cr.modify(|_, w| w.pllon().set_bit());
 
             // Wait for PLL to stabilise
             while rcc.cr.read().pllrdy().bit_is_clear() {}
 
-            // Set scaling factors and switch clock to PLL
-            rcc.cfgr.modify(|_, w| unsafe {
-                w.ppre2()
-                    .bits(ppre2_bits)
-                    .ppre1()
-                    .bits(ppre1_bits)
-                    .hpre()
-                    .bits(0)
-                    .sw()
-                    .pll()
+            // Wait for the voltage scale to be applied
+            while pwr.csr1.read().vosrdy().bit_is_clear() {}
+        } else if self.plli2s.is_some() || self.pllsai.is_some() {
+            // Only the shared input divider and source are needed by the auxiliary PLLs
+            rcc.pllcfgr.modify(|_, w| unsafe {
+                w.pllm().bits(setup.pllm).pllsrc().bit(self.hse.is_some())
             });
+        }
 
-            Clocks {
-                hclk: Hertz(hclk),
-                pclk1: Hertz(pclk1),
-                pclk2: Hertz(pclk2),
-                ppre1,
-                ppre2,
-                sysclk: Hertz(sysclk),
+        if let Some(plli2s) = self.plli2s {
+            rcc.plli2scfgr.write(|w| unsafe { w.bits(plli2s.bits()) });
+            dckcfgr1(rcc).modify(|r, w| unsafe {
+                w.bits((r.bits() & !0x1f) | u32::from(self.plli2s_divq - 1))
+            });
+
+            // Enable PLLI2S and wait for it to stabilise
+            rcc.cr.modify(|_, w| w.plli2son().set_bit());
+            while rcc.cr.read().plli2srdy().bit_is_clear() {}
+        }
+
+        if let Some(pllsai) = self.pllsai {
+            let (divr_mask, divr_bits) = match pllsai.r {
+                // LTDC divider is encoded as the power of two minus one
+                Some(_) => (0b11 << 16, (self.pllsai_divr.trailing_zeros() - 1) << 16),
+                None => (0, 0),
+            };
+
+            rcc.pllsaicfgr.write(|w| unsafe { w.bits(pllsai.bits()) });
+            dckcfgr1(rcc).modify(|r, w| unsafe {
+                w.bits(
+                    (r.bits() & !(0x1f << 8 | divr_mask))
+                        | u32::from(self.pllsai_divq - 1) << 8
+                        | divr_bits,
+                )
+            });
+
+            // Enable PLLSAI and wait for it to stabilise
+            rcc.cr.modify(|_, w| w.pllsaion().set_bit());
+            while rcc.cr.read().pllsairdy().bit_is_clear() {}
+        }
+
+        if setup.overdrive {
+            // Enable the over-drive regulator
+            pwr.cr1.modify(|_, w| w.oden().set_bit());
+            while pwr.csr1.read().odrdy().bit_is_clear() {}
+
+            // Switch the voltage regulator to over-drive mode
+            pwr.cr1.modify(|_, w| w.odswen().set_bit());
+            while pwr.csr1.read().odswrdy().bit_is_clear() {}
+        }
+
+        // Adjust flash wait states, increasing them before switching to a faster clock
+        let sysclk = setup.clocks.sysclk.0;
+        let latency = ((sysclk - 1) / FLASH_LATENCY_STEP) as u8;
+        if latency > flash.acr.read().latency().bits() {
+            set_flash_latency(flash, latency);
+        }
+
+        // Set scaling factors and switch clock source
+        rcc.cfgr.modify(|_, w| unsafe {
+            let w = w
+                .ppre2()
+                .bits(setup.ppre2_bits)
+                .ppre1()
+                .bits(setup.ppre1_bits)
+                .hpre()
+                .bits(setup.hpre_bits);
+
+            if setup.use_pll {
+                w.sw().pll()
+            } else if self.hse.is_some() {
+                w.sw().hse()
+            } else {
+                w.sw().hsi()
             }
+        });
+
+        // Decrease flash wait states only once running from the slower clock
+        set_flash_latency(flash, latency);
+
+        if setup.lse {
+            // The LSE lives in the backup domain, which is write protected after reset
+            pwr.cr1.modify(|_, w| w.dbp().set_bit());
+            rcc.bdcr.modify(|_, w| w.lseon().set_bit());
+            while rcc.bdcr.read().lserdy().bit_is_clear() {}
+        }
+
+        if setup.lsi {
+            rcc.csr.modify(|_, w| w.lsion().set_bit());
+            while rcc.csr.read().lsirdy().bit_is_clear() {}
         }
+
+        dckcfgr2(rcc).modify(|r, w| unsafe {
+            w.bits((r.bits() & !KERNEL_CLOCK_MASK) | setup.kernel_clock_bits)
+        });
+
+        Ok(setup.clocks)
     }
 }
 
+/// Clock configuration error
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClockError {
+    /// The HSE frequency is outside of the supported range from 4 to 26 MHz
+    InvalidHse,
+    /// The requested SYSCLK can't be generated, contains the closest achievable frequency
+    Sysclk(Hertz),
+    /// The requested HCLK can't be generated, contains the closest achievable frequency
+    Hclk(Hertz),
+    /// The requested PCLK1 can't be generated, contains the closest achievable frequency
+    Pclk1(Hertz),
+    /// The requested PCLK2 can't be generated, contains the closest achievable frequency
+    Pclk2(Hertz),
+    /// PLL48CLK can't be generated at exactly 48 MHz
+    Pll48clk,
+    /// The PLLI2S settings are out of range
+    Plli2s,
+    /// The PLLSAI settings are out of range
+    Pllsai,
+}
+
+/// Register settings for a clock configuration
+struct ClockSetup {
+    pll: Option<PllConfig>,
+    use_pll: bool,
+    pllm: u8,
+    vos: VoltageScale,
+    overdrive: bool,
+    hpre_bits: u8,
+    ppre1_bits: u8,
+    ppre2_bits: u8,
+    lse: bool,
+    lsi: bool,
+    kernel_clock_bits: u32,
+    clocks: Clocks,
+}
+
+/// Main internal regulator output voltage scale
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum VoltageScale {
+    Scale1,
+    Scale2,
+    Scale3,
+}
+
+/// Main PLL dividers and multiplier
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct PllConfig {
+    m: u8,
+    n: u16,
+    p: u8,
+    q: u8,
+}
+
+impl PllConfig {
+    /// Searches for PLL settings generating `sysclk` out of `input`
+    ///
+    /// Without a `sysclk` the main output is unused and any valid setting will do. If `pll48clk`
+    /// is set only settings generating exactly 48 MHz on the Q output are considered.
+    fn solve(
+        input: u32,
+        sysclk: Option<u32>,
+        pll48clk: bool,
+        max: u32,
+    ) -> Result<PllConfig, ClockError> {
+        let mut closest: Option<u32> = None;
+
+        // Walk up from the smallest input divisor so the highest VCO input frequency, which
+        // gives the lowest jitter, is preferred
+        for m in 2..=63_u32 {
+            // VCO input frequency must be in the range from 1 to 2 MHz
+            if input < m * 1_000_000 || input > m * 2_000_000 {
+                continue;
+            }
+
+            for n in 50..=432_u32 {
+                // VCO output frequency must be in the range from 100 to 432 MHz
+                let vco = u64::from(input) * u64::from(n) / u64::from(m);
+                if !(100_000_000..=432_000_000).contains(&vco) {
+                    continue;
+                }
+
+                let vco_exact = u64::from(input) * u64::from(n) % u64::from(m) == 0;
+                let q = if pll48clk {
+                    if !vco_exact || vco % u64::from(PLL48CLK) != 0 {
+                        continue;
+                    }
+                    vco / u64::from(PLL48CLK)
+                } else {
+                    // Stay at or below 48 MHz so the peripherals are within their limits
+                    (vco + u64::from(PLL48CLK) - 1) / u64::from(PLL48CLK)
+                };
+                if !(2..=15).contains(&q) {
+                    continue;
+                }
+
+                for &p in &[2_u32, 4, 6, 8] {
+                    let output = (vco / u64::from(p)) as u32;
+                    if output > max {
+                        continue;
+                    }
+
+                    let config = PllConfig {
+                        m: m as u8,
+                        n: n as u16,
+                        p: p as u8,
+                        q: q as u8,
+                    };
+
+                    let sysclk = match sysclk {
+                        Some(sysclk) => sysclk,
+                        None => return Ok(config),
+                    };
+
+                    let exact = u64::from(input) * u64::from(n) % u64::from(m * p) == 0;
+                    if exact && output == sysclk {
+                        return Ok(config);
+                    }
+
+                    closest = match closest {
+                        Some(c) if c.abs_diff(sysclk) <= output.abs_diff(sysclk) => Some(c),
+                        _ => Some(output),
+                    };
+                }
+            }
+        }
+
+        match closest {
+            Some(closest) => Err(ClockError::Sysclk(Hertz(closest))),
+            None if pll48clk => Err(ClockError::Pll48clk),
+            None => Err(ClockError::Sysclk(Hertz(0))),
+        }
+    }
+
+    /// Returns the frequency of the Q output for the given PLL input frequency
+    fn pll48clk(&self, input: u32) -> Hertz {
+        Hertz((u64::from(input) * u64::from(self.n) / u64::from(self.m) / u64::from(self.q)) as u32)
+    }
+}
+
+/// Auxiliary PLL multiplier and dividers
+///
+/// The P and R dividers are `None` on the devices lacking those outputs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct AuxPllConfig {
+    n: u16,
+    p: Option<u8>,
+    q: u8,
+    r: Option<u8>,
+}
+
+impl AuxPllConfig {
+    /// Checks the settings against the limits, given the VCO output frequency they result in
+    fn is_valid(&self, vco: u64) -> bool {
+        let p_valid = self.p.map_or(true, |p| matches!(p, 2 | 4 | 6 | 8));
+        let r_valid = self.r.map_or(true, |r| (2..=7).contains(&r));
+
+        (50..=432).contains(&self.n)
+            && (100_000_000..=432_000_000).contains(&vco)
+            && p_valid
+            && (2..=15).contains(&self.q)
+            && r_valid
+    }
+
+    /// Returns the frequencies of the P, Q and R outputs
+    fn outputs(&self, vco: u64) -> (Option<Hertz>, Hertz, Option<Hertz>) {
+        (
+            self.p.map(|p| Hertz((vco / u64::from(p)) as u32)),
+            Hertz((vco / u64::from(self.q)) as u32),
+            self.r.map(|r| Hertz((vco / u64::from(r)) as u32)),
+        )
+    }
+
+    /// Returns the register value, which has the same layout for PLLI2SCFGR and PLLSAICFGR
+    ///
+    /// The fields of missing outputs are left at zero, as they are reserved.
+    fn bits(&self) -> u32 {
+        u32::from(self.n) << 6
+            | self.p.map_or(0, |p| u32::from(p / 2 - 1) << 16)
+            | u32::from(self.q) << 24
+            | self.r.map_or(0, |r| u32::from(r) << 28)
+    }
+}
+
+/// Picks the input divider for the auxiliary PLLs if the main PLL isn't used
+///
+/// Prefers the highest VCO input frequency which is an exact division of the input
+fn aux_pllm(input: u32) -> u32 {
+    let mut valid = (2..=63).filter(|m| input >= m * 1_000_000 && input <= m * 2_000_000);
+    let first = valid.clone().next().unwrap_or(2);
+
+    valid.find(|m| input % m == 0).unwrap_or(first)
+}
+
+/// Kernel clock source of a USART or UART
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UsartClock {
+    /// APB clock of the peripheral, the reset default
+    Pclk = 0b00,
+    /// System clock
+    Sysclk = 0b01,
+    /// High speed internal oscillator, keeps running in Stop mode to wake up the MCU
+    Hsi = 0b10,
+    /// Low speed external oscillator, keeps running in Stop mode to wake up the MCU
+    Lse = 0b11,
+}
+
+/// Kernel clock source of an I2C peripheral
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum I2cClock {
+    /// APB1 clock, the reset default
+    Pclk = 0b00,
+    /// System clock
+    Sysclk = 0b01,
+    /// High speed internal oscillator
+    Hsi = 0b10,
+}
+
+/// Kernel clock source of the low power timer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LptimClock {
+    /// APB1 clock, the reset default
+    Pclk = 0b00,
+    /// Low speed internal oscillator
+    Lsi = 0b01,
+    /// High speed internal oscillator
+    Hsi = 0b10,
+    /// Low speed external oscillator
+    Lse = 0b11,
+}
+
+/// UxSEL, I2CxSEL and LPTIM1SEL fields of the DCKCFGR2 register
+const KERNEL_CLOCK_MASK: u32 = 0x03ff_ffff;
+
+/// Decodes the kernel clock frequencies of the U(S)ARTs, I2Cs and LPTIM1 from the DCKCFGR2 bits
+fn kernel_clocks(
+    bits: u32,
+    sysclk: Hertz,
+    pclk1: Hertz,
+    pclk2: Hertz,
+) -> ([Hertz; 8], [Hertz; 4], Hertz) {
+    // USART1 and USART6 sit on the APB2, all other U(S)ARTs on the APB1
+    let usart_pclks = [pclk2, pclk1, pclk1, pclk1, pclk1, pclk2, pclk1, pclk1];
+    let mut usart_clks = [pclk1; 8];
+    for (i, clk) in usart_clks.iter_mut().enumerate() {
+        *clk = match (bits >> (2 * i)) & 0b11 {
+            0b00 => usart_pclks[i],
+            0b01 => sysclk,
+            0b10 => Hertz(HSI),
+            _ => Hertz(LSE),
+        };
+    }
+
+    let mut i2c_clks = [pclk1; 4];
+    for (i, clk) in i2c_clks.iter_mut().enumerate() {
+        *clk = match (bits >> (16 + 2 * i)) & 0b11 {
+            0b00 => pclk1,
+            0b01 => sysclk,
+            _ => Hertz(HSI),
+        };
+    }
+
+    let lptim1clk = match (bits >> 24) & 0b11 {
+        0b00 => pclk1,
+        0b01 => Hertz(LSI),
+        0b10 => Hertz(HSI),
+        _ => Hertz(LSE),
+    };
+
+    (usart_clks, i2c_clks, lptim1clk)
+}
+
+/// Whether PLLI2S has the P output for SPDIFRX and PLLSAI the R output for the LTDC
+#[cfg(any(feature = "stm32f7x2", feature = "stm32f7x3"))]
+const SPDIFRX_LTDC_OUTPUTS: bool = false;
+
+/// Whether PLLI2S has the P output for SPDIFRX and PLLSAI the R output for the LTDC
+#[cfg(not(any(feature = "stm32f7x2", feature = "stm32f7x3")))]
+const SPDIFRX_LTDC_OUTPUTS: bool = true;
+
+/// Returns the dedicated clocks configuration register 1
+#[cfg(any(feature = "stm32f7x2", feature = "stm32f7x3"))]
+fn dckcfgr1(rcc: &RegisterBlock) -> &::stm32::rcc::DCKCFGR1 {
+    &rcc.dckcfgr1
+}
+
+/// Returns the dedicated clocks configuration register 1
+#[cfg(not(any(feature = "stm32f7x2", feature = "stm32f7x3")))]
+fn dckcfgr1(rcc: &RegisterBlock) -> &::stm32::rcc::DKCFGR1 {
+    &rcc.dkcfgr1
+}
+
+/// AHB prescaler dividers and their `HPRE` bits
+const HPRE_DIVIDERS: [(u32, u8); 9] = [
+    (1, 0b0000),
+    (2, 0b1000),
+    (4, 0b1001),
+    (8, 0b1010),
+    (16, 0b1011),
+    (64, 0b1100),
+    (128, 0b1101),
+    (256, 0b1110),
+    (512, 0b1111),
+];
+
+/// APB prescaler dividers and their `PPREx` bits
+const PPRE_DIVIDERS: [(u32, u8); 5] = [(1, 0b000), (2, 0b100), (4, 0b101), (8, 0b110), (16, 0b111)];
+
+/// Sets the number of flash wait states
+#[allow(unused_unsafe)]
+fn set_flash_latency(flash: &::stm32::flash::RegisterBlock, latency: u8) {
+    // Writing LATENCY is only unsafe for some of the devices
+    flash
+        .acr
+        .modify(|_, w| unsafe { w.latency().bits(latency) });
+}
+
+/// Picks the prescaler out of `dividers` that derives `requested` from `input`
+///
+/// Without a request the smallest divider resulting in a frequency of at most `max` is used.
+/// Returns the closest achievable frequency not exceeding `max` if there is no exact solution.
+fn select_divider(
+    input: u32,
+    requested: Option<u32>,
+    max: u32,
+    dividers: &[(u32, u8)],
+) -> Result<(u32, u8), Hertz> {
+    let mut valid = dividers.iter().filter(|&&(div, _)| input / div <= max);
+
+    let requested = match requested {
+        Some(requested) => requested,
+        None => return valid.next().cloned().ok_or(Hertz(0)),
+    };
+
+    let mut closest: Option<u32> = None;
+    for &(div, bits) in valid {
+        let output = input / div;
+        if output == requested && input % div == 0 {
+            return Ok((div, bits));
+        }
+
+        closest = match closest {
+            Some(c) if c.abs_diff(requested) <= output.abs_diff(requested) => Some(c),
+            _ => Some(output),
+        };
+    }
+
+    Err(Hertz(closest.unwrap_or(0)))
+}
+
+/// Returns the dedicated clocks configuration register 2
+#[cfg(any(feature = "stm32f7x2", feature = "stm32f7x3"))]
+fn dckcfgr2(rcc: &RegisterBlock) -> &::stm32::rcc::DCKCFGR2 {
+    &rcc.dckcfgr2
+}
+
+/// Returns the dedicated clocks configuration register 2
+#[cfg(not(any(feature = "stm32f7x2", feature = "stm32f7x3")))]
+fn dckcfgr2(rcc: &RegisterBlock) -> &::stm32::rcc::DKCFGR2 {
+    &rcc.dkcfgr2
+}
+
 /// Frozen clock frequencies
 ///
 /// The existence of this value indicates that the clock configuration can no longer be changed
 #[derive(Clone, Copy)]
 pub struct Clocks {
+    hse: Option<Hertz>,
     hclk: Hertz,
     pclk1: Hertz,
     pclk2: Hertz,
     ppre1: u8,
     ppre2: u8,
     sysclk: Hertz,
+    pll48clk: Option<Hertz>,
+    plli2s: Option<(Option<Hertz>, Hertz, Option<Hertz>)>,
+    plli2s_divq: Option<Hertz>,
+    pllsai: Option<(Option<Hertz>, Hertz, Option<Hertz>)>,
+    pllsai_divq: Option<Hertz>,
+    pllsai_divr: Option<Hertz>,
+    usart_clks: [Hertz; 8],
+    i2c_clks: [Hertz; 4],
+    lptim1clk: Hertz,
 }
 
 impl Clocks {
+    /// Returns the frequency of the HSE oscillator, or `None` if the HSI is used
+    pub fn hse(&self) -> Option<Hertz> {
+        self.hse
+    }
+
     /// Returns the frequency of the AHB1
     pub fn hclk(&self) -> Hertz {
         self.hclk
@@ -266,4 +1146,421 @@ impl Clocks {
     pub fn sysclk(&self) -> Hertz {
         self.sysclk
     }
+
+    /// Returns the frequency of the PLL48CLK domain, or `None` if the PLL is not running
+    pub fn pll48clk(&self) -> Option<Hertz> {
+        self.pll48clk
+    }
+
+    /// Returns the frequency of the PLLI2S P output (SPDIFRX), or `None` if PLLI2S is off or
+    /// the device has no SPDIFRX
+    pub fn plli2s_p(&self) -> Option<Hertz> {
+        self.plli2s.and_then(|(p, _, _)| p)
+    }
+
+    /// Returns the frequency of the PLLI2S Q output, or `None` if PLLI2S is off
+    pub fn plli2s_q(&self) -> Option<Hertz> {
+        self.plli2s.map(|(_, q, _)| q)
+    }
+
+    /// Returns the frequency of the PLLI2S R output (I2S), or `None` if PLLI2S is off
+    pub fn plli2s_r(&self) -> Option<Hertz> {
+        self.plli2s.and_then(|(_, _, r)| r)
+    }
+
+    /// Returns the frequency of the PLLI2S Q output after the SAI divider, or `None` if PLLI2S
+    /// is off
+    pub fn plli2s_divq(&self) -> Option<Hertz> {
+        self.plli2s_divq
+    }
+
+    /// Returns the frequency of the PLLSAI P output (alternate 48 MHz), or `None` if PLLSAI is
+    /// off
+    pub fn pllsai_p(&self) -> Option<Hertz> {
+        self.pllsai.and_then(|(p, _, _)| p)
+    }
+
+    /// Returns the frequency of the PLLSAI Q output, or `None` if PLLSAI is off
+    pub fn pllsai_q(&self) -> Option<Hertz> {
+        self.pllsai.map(|(_, q, _)| q)
+    }
+
+    /// Returns the frequency of the PLLSAI R output, or `None` if PLLSAI is off or the device
+    /// has no LTDC
+    pub fn pllsai_r(&self) -> Option<Hertz> {
+        self.pllsai.and_then(|(_, _, r)| r)
+    }
+
+    /// Returns the frequency of the PLLSAI Q output after the SAI divider, or `None` if PLLSAI
+    /// is off
+    pub fn pllsai_divq(&self) -> Option<Hertz> {
+        self.pllsai_divq
+    }
+
+    /// Returns the frequency of the PLLSAI R output after the LTDC divider, or `None` if PLLSAI
+    /// is off or the device has no LTDC
+    pub fn pllsai_divr(&self) -> Option<Hertz> {
+        self.pllsai_divr
+    }
+
+    kernel_clock_accessors! {
+        usart1clk: (usart_clks[0], "Returns the kernel clock frequency of USART1"),
+        usart2clk: (usart_clks[1], "Returns the kernel clock frequency of USART2"),
+        usart3clk: (usart_clks[2], "Returns the kernel clock frequency of USART3"),
+        uart4clk: (usart_clks[3], "Returns the kernel clock frequency of UART4"),
+        uart5clk: (usart_clks[4], "Returns the kernel clock frequency of UART5"),
+        usart6clk: (usart_clks[5], "Returns the kernel clock frequency of USART6"),
+        uart7clk: (usart_clks[6], "Returns the kernel clock frequency of UART7"),
+        uart8clk: (usart_clks[7], "Returns the kernel clock frequency of UART8"),
+        i2c1clk: (i2c_clks[0], "Returns the kernel clock frequency of I2C1"),
+        i2c2clk: (i2c_clks[1], "Returns the kernel clock frequency of I2C2"),
+        i2c3clk: (i2c_clks[2], "Returns the kernel clock frequency of I2C3"),
+        i2c4clk: (i2c_clks[3], "Returns the kernel clock frequency of I2C4"),
+    }
+
+    /// Returns the kernel clock frequency of LPTIM1
+    pub fn lptim1clk(&self) -> Hertz {
+        self.lptim1clk
+    }
+}
+
+/// Clock source of the microcontroller clock output 1 (MCO1)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mco1Source {
+    /// High speed internal oscillator
+    Hsi = 0b00,
+    /// Low speed external oscillator
+    Lse = 0b01,
+    /// High speed external oscillator
+    Hse = 0b10,
+    /// Main PLL P output
+    Pll = 0b11,
+}
+
+/// Clock source of the microcontroller clock output 2 (MCO2)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mco2Source {
+    /// System clock
+    Sysclk = 0b00,
+    /// PLLI2S R output
+    Plli2s = 0b01,
+    /// High speed external oscillator
+    Hse = 0b10,
+    /// Main PLL P output
+    Pll = 0b11,
+}
+
+/// Division factor between the selected source and a clock output pin
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum McoPrescaler {
+    Div1 = 0b000,
+    Div2 = 0b100,
+    Div3 = 0b101,
+    Div4 = 0b110,
+    Div5 = 0b111,
+}
+
+/// Microcontroller clock output 1 on PA8
+///
+/// The selected source has to be running; it's not enabled by this driver. Configure the pin
+/// speed to match the output frequency.
+pub struct Mco1 {
+    pin: PA8<Alternate<AF0>>,
+}
+
+impl Mco1 {
+    /// Outputs `source` divided by `prescaler` on PA8
+    pub fn new(pin: PA8<Alternate<AF0>>, source: Mco1Source, prescaler: McoPrescaler) -> Self {
+        // NOTE(unsafe) MCO1 and MCO1PRE are owned by the PA8 pin passed in
+        let rcc = unsafe { &*RCC::ptr() };
+        rcc.cfgr.modify(|r, w| unsafe {
+            w.bits(
+                (r.bits() & !((0b11 << 21) | (0b111 << 24)))
+                    | ((source as u32) << 21)
+                    | ((prescaler as u32) << 24),
+            )
+        });
+
+        Mco1 { pin }
+    }
+
+    /// Releases the pin, leaving the clock output running
+    pub fn free(self) -> PA8<Alternate<AF0>> {
+        self.pin
+    }
+}
+
+/// Microcontroller clock output 2 on PC9
+///
+/// The selected source has to be running; it's not enabled by this driver. Configure the pin
+/// speed to match the output frequency.
+pub struct Mco2 {
+    pin: PC9<Alternate<AF0>>,
+}
+
+impl Mco2 {
+    /// Outputs `source` divided by `prescaler` on PC9
+    pub fn new(pin: PC9<Alternate<AF0>>, source: Mco2Source, prescaler: McoPrescaler) -> Self {
+        // NOTE(unsafe) MCO2 and MCO2PRE are owned by the PC9 pin passed in
+        let rcc = unsafe { &*RCC::ptr() };
+        rcc.cfgr.modify(|r, w| unsafe {
+            w.bits(
+                (r.bits() & !((0b11 << 30) | (0b111 << 27)))
+                    | ((source as u32) << 30)
+                    | ((prescaler as u32) << 27),
+            )
+        });
+
+        Mco2 { pin }
+    }
+
+    /// Releases the pin, leaving the clock output running
+    pub fn free(self) -> PC9<Alternate<AF0>> {
+        self.pin
+    }
+}
+
+/// Returns `true` if the clock security system detected a failure of the HSE
+pub fn css_failure() -> bool {
+    // NOTE(unsafe) atomic read without side effects
+    let rcc = unsafe { &*RCC::ptr() };
+    rcc.cir.read().cssf().bit_is_set()
+}
+
+/// Clears the clock security system interrupt flag
+pub fn clear_css_failure() {
+    // NOTE(unsafe) CSSC is a write-only bit, all other fields are written back unchanged
+    let rcc = unsafe { &*RCC::ptr() };
+    rcc.cir.modify(|_, w| w.cssc().set_bit());
+}
+
+/// Handles an HSE failure detected by the clock security system
+///
+/// Call this from the `NMI` exception handler. When the clock security system detects an HSE
+/// failure, the hardware has already switched SYSCLK to the HSI and stopped the HSE and the
+/// PLLs by the time the NMI is taken. If the NMI was raised by the clock security system, this
+/// clears the flag, which would otherwise retrigger the NMI, and returns the clock frequencies
+/// now in effect. The AHB and APB prescalers are left as configured, so all bus clocks drop
+/// accordingly; drivers have to be reinitialized with the returned `Clocks` to get correct baud
+/// rates and timings again.
+///
+/// Returns `None` if the NMI wasn't caused by the clock security system.
+pub fn handle_css_failure() -> Option<Clocks> {
+    if !css_failure() {
+        return None;
+    }
+
+    clear_css_failure();
+
+    // NOTE(unsafe) atomic reads without side effects
+    let rcc = unsafe { &*RCC::ptr() };
+    let cfgr = rcc.cfgr.read();
+
+    let hpre = match HPRE_DIVIDERS
+        .iter()
+        .find(|&&(_, bits)| bits == cfgr.hpre().bits())
+    {
+        Some(&(div, _)) => div,
+        None => 1,
+    };
+    let ppre = |bits| match PPRE_DIVIDERS.iter().find(|&&(_, b)| b == bits) {
+        Some(&(div, _)) => div,
+        None => 1,
+    };
+    let ppre1 = ppre(cfgr.ppre1().bits());
+    let ppre2 = ppre(cfgr.ppre2().bits());
+
+    let sysclk = Hertz(HSI);
+    let hclk = HSI / hpre;
+    let pclk1 = Hertz(hclk / ppre1);
+    let pclk2 = Hertz(hclk / ppre2);
+
+    let (usart_clks, i2c_clks, lptim1clk) = kernel_clocks(
+        dckcfgr2(rcc).read().bits() & KERNEL_CLOCK_MASK,
+        sysclk,
+        pclk1,
+        pclk2,
+    );
+
+    Some(Clocks {
+        hse: None,
+        hclk: Hertz(hclk),
+        pclk1,
+        pclk2,
+        ppre1: ppre1 as u8,
+        ppre2: ppre2 as u8,
+        sysclk,
+        pll48clk: None,
+        plli2s: None,
+        plli2s_divq: None,
+        pllsai: None,
+        pllsai_divq: None,
+        pllsai_divr: None,
+        usart_clks,
+        i2c_clks,
+        lptim1clk,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        select_divider, AuxPllConfig, ClockError, PllConfig, HSI, PLL48CLK, PPRE_DIVIDERS,
+        SYSCLK_MAX,
+    };
+    use time::Hertz;
+
+    /// Checks `config` against the PLL limits and returns the resulting SYSCLK
+    fn check_limits(input: u32, config: PllConfig) -> u32 {
+        let (m, n, p) = (
+            u64::from(config.m),
+            u64::from(config.n),
+            u64::from(config.p),
+        );
+
+        let vco_input = u64::from(input) / m;
+        assert!(
+            (1_000_000..=2_000_000).contains(&vco_input),
+            "{:?}: VCO input {} Hz",
+            config,
+            vco_input
+        );
+        assert!((50..=432).contains(&n), "{:?}: PLLN", config);
+        let vco = u64::from(input) * n / m;
+        assert!(
+            (100_000_000..=432_000_000).contains(&vco),
+            "{:?}: VCO output {} Hz",
+            config,
+            vco
+        );
+        assert!((2..=15).contains(&config.q), "{:?}: PLLQ", config);
+
+        let sysclk = (vco / p) as u32;
+        assert!(sysclk <= SYSCLK_MAX, "{:?}: SYSCLK {} Hz", config, sysclk);
+        sysclk
+    }
+
+    #[test]
+    fn pll_within_limits() {
+        for &input in &[
+            HSI, 4_000_000, 8_000_000, 12_000_000, 25_000_000, 26_000_000,
+        ] {
+            for sysclk in (24..=216).map(|mhz| mhz * 1_000_000) {
+                match PllConfig::solve(input, Some(sysclk), false, SYSCLK_MAX) {
+                    Ok(config) => assert_eq!(check_limits(input, config), sysclk),
+                    Err(ClockError::Sysclk(Hertz(closest))) => {
+                        assert_ne!(closest, sysclk);
+                        assert!(closest > 0 && closest <= SYSCLK_MAX);
+                    }
+                    Err(e) => panic!("{} Hz from {} Hz: {:?}", sysclk, input, e),
+                }
+            }
+
+            // The main output doesn't matter if only the 48 MHz domain is needed
+            let config = PllConfig::solve(input, None, true, SYSCLK_MAX).unwrap();
+            check_limits(input, config);
+            assert_eq!(config.pll48clk(input), Hertz(PLL48CLK));
+        }
+    }
+
+    #[test]
+    fn pll_input_out_of_range() {
+        // No input divider gets the VCO input into the range from 1 to 2 MHz
+        assert_eq!(
+            PllConfig::solve(1_000_000, Some(100_000_000), false, SYSCLK_MAX),
+            Err(ClockError::Sysclk(Hertz(0)))
+        );
+        assert_eq!(
+            PllConfig::solve(1_000_000, None, true, SYSCLK_MAX),
+            Err(ClockError::Pll48clk)
+        );
+    }
+
+    #[test]
+    fn pll_unreachable() {
+        // Below the lowest VCO output frequency divided by the largest PLLP
+        assert_eq!(
+            PllConfig::solve(HSI, Some(1_000_000), false, SYSCLK_MAX),
+            Err(ClockError::Sysclk(Hertz(12_500_000)))
+        );
+        // Above the limit
+        assert_eq!(
+            PllConfig::solve(HSI, Some(300_000_000), false, SYSCLK_MAX),
+            Err(ClockError::Sysclk(Hertz(SYSCLK_MAX)))
+        );
+    }
+
+    #[test]
+    fn pll_closest() {
+        assert_eq!(
+            PllConfig::solve(HSI, Some(100_000_001), false, SYSCLK_MAX),
+            Err(ClockError::Sysclk(Hertz(100_000_000)))
+        );
+        assert_eq!(
+            PllConfig::solve(HSI, Some(99_999_999), false, SYSCLK_MAX),
+            Err(ClockError::Sysclk(Hertz(100_000_000)))
+        );
+
+        // A VCO output frequency of 216 MHz can't be divided down to exactly 48 MHz
+        let config = PllConfig::solve(25_000_000, Some(216_000_000), true, SYSCLK_MAX).unwrap();
+        assert_eq!(check_limits(25_000_000, config), 216_000_000);
+        assert_eq!(config.pll48clk(25_000_000), Hertz(PLL48CLK));
+    }
+
+    #[test]
+    fn divider_selection() {
+        // Without a request the smallest divider within the limit is used
+        assert_eq!(
+            select_divider(216_000_000, None, 54_000_000, &PPRE_DIVIDERS),
+            Ok((4, 0b101))
+        );
+        assert_eq!(
+            select_divider(16_000_000, None, 54_000_000, &PPRE_DIVIDERS),
+            Ok((1, 0b000))
+        );
+
+        assert_eq!(
+            select_divider(216_000_000, Some(27_000_000), 54_000_000, &PPRE_DIVIDERS),
+            Ok((8, 0b110))
+        );
+
+        // The closest frequency within the limit is reported otherwise
+        assert_eq!(
+            select_divider(100_000_000, Some(30_000_000), 54_000_000, &PPRE_DIVIDERS),
+            Err(Hertz(25_000_000))
+        );
+        assert_eq!(
+            select_divider(216_000_000, Some(108_000_000), 54_000_000, &PPRE_DIVIDERS),
+            Err(Hertz(54_000_000))
+        );
+        assert_eq!(
+            select_divider(200_000_000, Some(1_000_000), 54_000_000, &PPRE_DIVIDERS),
+            Err(Hertz(12_500_000))
+        );
+    }
+
+    #[test]
+    fn aux_pll_missing_outputs() {
+        let config = AuxPllConfig {
+            n: 192,
+            p: None,
+            q: 4,
+            r: Some(2),
+        };
+        assert!(config.is_valid(192_000_000));
+        assert_eq!(config.bits(), 192 << 6 | 4 << 24 | 2 << 28);
+        assert_eq!(
+            config.outputs(192_000_000),
+            (None, Hertz(48_000_000), Some(Hertz(96_000_000)))
+        );
+
+        let config = AuxPllConfig {
+            n: 192,
+            p: Some(4),
+            q: 4,
+            r: None,
+        };
+        assert!(config.is_valid(192_000_000));
+        assert_eq!(config.bits(), 192 << 6 | 1 << 16 | 4 << 24);
+    }
 }
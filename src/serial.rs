@@ -1,6 +1,5 @@
 use core::fmt;
 use core::marker::PhantomData;
-use core::ptr;
 
 use hal;
 use hal::prelude::*;
@@ -30,26 +29,17 @@ use nb::block;
     feature = "stm32f7x7",
     feature = "stm32f7x9",
 ))]
-use stm32::{RCC, UART4, UART5, UART7, UART8, USART1, USART2, USART3, USART6};
-
-#[cfg(any(
-    feature = "stm32f7x2",
-    feature = "stm32f7x3",
-    feature = "stm32f7x5",
-    feature = "stm32f7x6",
-    feature = "stm32f7x7",
-    feature = "stm32f7x9",
-))]
-use stm32::usart6::cr2::STOPW;
-
-// #[cfg(any(feature = "stm32f401", feature = "stm32f412", feature = "stm32f411"))]
-// use stm32::usart1::cr2::STOPW;
+use stm32::{UART4, UART5, UART7, UART8, USART1, USART2, USART3, USART6};
 
 use gpio::gpioa::{PA10, PA2, PA3, PA9};
 use gpio::gpiob::{PB6, PB7};
 use gpio::gpioc::{PC6, PC7};
 use gpio::gpiod::{PD5, PD6};
 
+use dma::{self, dma1, dma2, C4, C5, C7};
+use dma::{
+    MemoryToPeripheral, PeripheralToMemory, ReadBuffer, Stream, Target, Transfer, WriteBuffer,
+};
 #[cfg(any(
     feature = "stm32f7x2",
     feature = "stm32f7x3",
@@ -141,7 +131,7 @@ use gpio::gpiof::{PF6, PF7};
 ))]
 use gpio::gpiog::{PG14, PG9};
 use gpio::{Alternate, AF7, AF8};
-use rcc::Clocks;
+use rcc::{Clocks, Enable, APB1, APB2};
 
 /// Serial error
 #[derive(Debug)]
@@ -173,6 +163,7 @@ pub mod config {
     use time::U32Ext;
 
     pub enum WordLength {
+        DataBits7,
         DataBits8,
         DataBits9,
     }
@@ -222,6 +213,11 @@ pub mod config {
             self
         }
 
+        pub fn wordlength_7(mut self) -> Self {
+            self.wordlength = WordLength::DataBits7;
+            self
+        }
+
         pub fn wordlength_8(mut self) -> Self {
             self.wordlength = WordLength::DataBits8;
             self
@@ -376,7 +372,7 @@ pub struct Tx<USART> {
 
 macro_rules! halUsart {
     ($(
-        $USARTX:ident: ($usartX:ident, $apbXenr:ident, $usartXen:ident,  $pclkX:ident),
+        $USARTX:ident: ($usartX:ident, $APBX:ident, $usartXclk:ident),
     )+) => {
         $(
             impl<PINS> Serial<$USARTX, PINS> {
@@ -385,25 +381,27 @@ macro_rules! halUsart {
                     pins: PINS,
                     config: config::Config,
                     clocks: Clocks,
+                    apb: &mut $APBX,
                 ) -> Result<Self, config::InvalidConfig>
                 where
                     PINS: Pins<$USARTX>,
                 {
                     use self::config::*;
 
-                    // NOTE(unsafe) This executes only during initialisation
-                    let rcc = unsafe { &(*RCC::ptr()) };
-
                     // Enable clock for USART
-                    rcc.$apbXenr.modify(|_, w| w.$usartXen().set_bit());
+                    $USARTX::enable(apb);
 
-                    // Calculate correct baudrate divisor on the fly
-                    let div = (clocks.$pclkX().0 * 25) / (4 * config.baudrate.0);
-                    let mantissa = div / 100;
-                    let fraction = ((div - mantissa * 100) * 16 + 50) / 100;
-                    usart
-                        .brr
-                        .write(|w| unsafe { w.bits(mantissa << 4 | fraction) });
+                    // Calculate correct baudrate divisor on the fly, rounded to the closest
+                    // value. With oversampling by 16 the divisor must be at least 16.
+                    let baudrate = config.baudrate.0;
+                    if baudrate == 0 {
+                        return Err(InvalidConfig);
+                    }
+                    let div = (clocks.$usartXclk().0 + baudrate / 2) / baudrate;
+                    if !(16..=0xffff).contains(&div) {
+                        return Err(InvalidConfig);
+                    }
+                    usart.brr.write(|w| unsafe { w.bits(div) });
 
                     // Reset other registers to disable advanced USART features
                     usart.cr2.reset();
@@ -418,10 +416,14 @@ macro_rules! halUsart {
                             .set_bit()
                             .re()
                             .set_bit()
-                            .m()
+                            .m1()
+                            .bit(match config.wordlength {
+                                WordLength::DataBits7 => true,
+                                _ => false,
+                            }).m0()
                             .bit(match config.wordlength {
-                                WordLength::DataBits8 => false,
                                 WordLength::DataBits9 => true,
+                                _ => false,
                             }).pce()
                             .bit(match config.parity {
                                 Parity::ParityNone => false,
@@ -434,12 +436,13 @@ macro_rules! halUsart {
                     });
 
                     usart.cr2.write(|w| {
-                        w.stop().variant(match config.stopbits {
-                            StopBits::STOP0P5 => STOPW::STOP0P5,
-                            StopBits::STOP1 => STOPW::STOP1,
-                            StopBits::STOP1P5 => STOPW::STOP1P5,
-                            StopBits::STOP2 => STOPW::STOP2,
-                        })
+                        let stop = w.stop();
+                        match config.stopbits {
+                            StopBits::STOP0P5 => stop.bit_0_5(),
+                            StopBits::STOP1 => stop.bit_1(),
+                            StopBits::STOP1P5 => stop.bit_1_5(),
+                            StopBits::STOP2 => stop.bit_2(),
+                        }
                     });
                     Ok(Serial { usart, pins })
                 }
@@ -489,33 +492,153 @@ macro_rules! halUsart {
                 }
             }
 
+            impl Tx<$USARTX> {
+                /// Starts writing the contents of `buffer` using DMA
+                ///
+                /// The transfer is complete once the last byte has been handed to the USART; use
+                /// `flush` afterwards to wait until it has been sent.
+                ///
+                /// Fails with `dma::Error::BufferLength` if `buffer` is empty or too long.
+                pub fn write_all<STREAM, BUFFER>(
+                    self,
+                    buffer: BUFFER,
+                    stream: STREAM,
+                ) -> Result<
+                    Transfer<STREAM, Self, BUFFER, MemoryToPeripheral>,
+                    (dma::Error, STREAM, Self, BUFFER),
+                >
+                where
+                    STREAM: Stream,
+                    Self: Target<STREAM, MemoryToPeripheral, Word = u8>,
+                    BUFFER: ReadBuffer<Word = u8>,
+                {
+                    // NOTE(unsafe) DMAT is only used by the transmitter
+                    unsafe { (*$USARTX::ptr()).cr3.modify(|_, w| w.dmat().set_bit()) };
+
+                    Transfer::write(stream, self, buffer, dma::Config::default())
+                }
+            }
+
+            impl Rx<$USARTX> {
+                /// Starts filling `buffer` with received bytes using DMA
+                ///
+                /// Fails with `dma::Error::BufferLength` if `buffer` is empty or too long.
+                pub fn read_exact<STREAM, BUFFER>(
+                    self,
+                    buffer: BUFFER,
+                    stream: STREAM,
+                ) -> Result<
+                    Transfer<STREAM, Self, BUFFER, PeripheralToMemory>,
+                    (dma::Error, STREAM, Self, BUFFER),
+                >
+                where
+                    STREAM: Stream,
+                    Self: Target<STREAM, PeripheralToMemory, Word = u8>,
+                    BUFFER: WriteBuffer<Word = u8>,
+                {
+                    // NOTE(unsafe) DMAR is only used by the receiver
+                    unsafe { (*$USARTX::ptr()).cr3.modify(|_, w| w.dmar().set_bit()) };
+
+                    Transfer::read(stream, self, buffer, dma::Config::default())
+                }
+
+                /// Starts receiving into `buffer` using DMA, wrapping around at its end
+                ///
+                /// Combined with idle line detection on the returned transfer this can receive
+                /// messages of unknown length: once the line goes idle, the bytes up to
+                /// `buffer.len() - transfer.remaining()` have been received.
+                ///
+                /// Fails with `dma::Error::BufferLength` if `buffer` is empty or too long.
+                pub fn read_circular<STREAM, BUFFER>(
+                    self,
+                    buffer: BUFFER,
+                    stream: STREAM,
+                ) -> Result<
+                    Transfer<STREAM, Self, BUFFER, PeripheralToMemory>,
+                    (dma::Error, STREAM, Self, BUFFER),
+                >
+                where
+                    STREAM: Stream,
+                    Self: Target<STREAM, PeripheralToMemory, Word = u8>,
+                    BUFFER: WriteBuffer<Word = u8>,
+                {
+                    // NOTE(unsafe) DMAR is only used by the receiver
+                    unsafe { (*$USARTX::ptr()).cr3.modify(|_, w| w.dmar().set_bit()) };
+
+                    Transfer::read(stream, self, buffer, dma::Config::default().circular())
+                }
+            }
+
+            impl<STREAM, BUFFER> Transfer<STREAM, Rx<$USARTX>, BUFFER, PeripheralToMemory>
+            where
+                STREAM: Stream,
+                Rx<$USARTX>: Target<STREAM, PeripheralToMemory>,
+            {
+                /// Starts listening for the `Idle` interrupt event
+                pub fn listen_idle(&mut self) {
+                    // NOTE(unsafe) IDLEIE is only used by the receiver
+                    unsafe { (*$USARTX::ptr()).cr1.modify(|_, w| w.idleie().set_bit()) }
+                }
+
+                /// Stops listening for the `Idle` interrupt event
+                pub fn unlisten_idle(&mut self) {
+                    // NOTE(unsafe) IDLEIE is only used by the receiver
+                    unsafe { (*$USARTX::ptr()).cr1.modify(|_, w| w.idleie().clear_bit()) }
+                }
+
+                /// Returns `true` if the line went idle after receiving data
+                pub fn is_idle(&self) -> bool {
+                    // NOTE(unsafe) atomic read with no side effects
+                    unsafe { (*$USARTX::ptr()).isr.read().idle().bit_is_set() }
+                }
+
+                /// Clears the idle line flag
+                pub fn clear_idle(&mut self) {
+                    // NOTE(unsafe) atomic write to a stateless register
+                    unsafe { (*$USARTX::ptr()).icr.write(|w| w.idlecf().set_bit()) }
+                }
+            }
+
             impl hal::serial::Read<u8> for Rx<$USARTX> {
                 type Error = Error;
 
                 fn read(&mut self) -> nb::Result<u8, Error> {
                     // NOTE(unsafe) atomic read with no side effects
-                    let sr = unsafe { (*$USARTX::ptr()).sr.read() };
-
-                    // Any error requires the dr to be read to clear
-                    if sr.pe().bit_is_set()
-                        || sr.fe().bit_is_set()
-                        || sr.nf().bit_is_set()
-                        || sr.ore().bit_is_set()
+                    let isr = unsafe { (*$USARTX::ptr()).isr.read() };
+
+                    // Error flags are cleared through the ICR, unlike on older families where
+                    // they were cleared by reading the data register
+                    if isr.pe().bit_is_set()
+                        || isr.fe().bit_is_set()
+                        || isr.nf().bit_is_set()
+                        || isr.ore().bit_is_set()
                     {
-                        unsafe { (*$USARTX::ptr()).dr.read() };
+                        // NOTE(unsafe) atomic write to a stateless register
+                        unsafe {
+                            (*$USARTX::ptr()).icr.write(|w| {
+                                w.pecf()
+                                    .set_bit()
+                                    .fecf()
+                                    .set_bit()
+                                    .ncf()
+                                    .set_bit()
+                                    .orecf()
+                                    .set_bit()
+                            })
+                        };
                     }
 
-                    Err(if sr.pe().bit_is_set() {
+                    Err(if isr.pe().bit_is_set() {
                         nb::Error::Other(Error::Parity)
-                    } else if sr.fe().bit_is_set() {
+                    } else if isr.fe().bit_is_set() {
                         nb::Error::Other(Error::Framing)
-                    } else if sr.nf().bit_is_set() {
+                    } else if isr.nf().bit_is_set() {
                         nb::Error::Other(Error::Noise)
-                    } else if sr.ore().bit_is_set() {
+                    } else if isr.ore().bit_is_set() {
                         nb::Error::Other(Error::Overrun)
-                    } else if sr.rxne().bit_is_set() {
-                        // NOTE(read_volatile) see `write_volatile` below
-                        return Ok(unsafe { ptr::read_volatile(&(*$USARTX::ptr()).dr as *const _ as *const _) });
+                    } else if isr.rxne().bit_is_set() {
+                        // NOTE(unsafe) atomic read, which clears RXNE
+                        return Ok(unsafe { (*$USARTX::ptr()).rdr.read().rdr().bits() as u8 });
                     } else {
                         nb::Error::WouldBlock
                     })
@@ -527,9 +650,9 @@ macro_rules! halUsart {
 
                 fn flush(&mut self) -> nb::Result<(), Self::Error> {
                     // NOTE(unsafe) atomic read with no side effects
-                    let sr = unsafe { (*$USARTX::ptr()).sr.read() };
+                    let isr = unsafe { (*$USARTX::ptr()).isr.read() };
 
-                    if sr.tc().bit_is_set() {
+                    if isr.tc().bit_is_set() {
                         Ok(())
                     } else {
                         Err(nb::Error::WouldBlock)
@@ -538,12 +661,11 @@ macro_rules! halUsart {
 
                 fn write(&mut self, byte: u8) -> nb::Result<(), Self::Error> {
                     // NOTE(unsafe) atomic read with no side effects
-                    let sr = unsafe { (*$USARTX::ptr()).sr.read() };
+                    let isr = unsafe { (*$USARTX::ptr()).isr.read() };
 
-                    if sr.txe().bit_is_set() {
+                    if isr.txe().bit_is_set() {
                         // NOTE(unsafe) atomic write to stateless register
-                        // NOTE(write_volatile) 8-bit write that's not possible through the svd2rust API
-                        unsafe { ptr::write_volatile(&(*$USARTX::ptr()).dr as *const _ as *mut _, byte) }
+                        unsafe { (*$USARTX::ptr()).tdr.write(|w| w.tdr().bits(u16::from(byte))) }
                         Ok(())
                     } else {
                         Err(nb::Error::WouldBlock)
@@ -555,14 +677,53 @@ macro_rules! halUsart {
 }
 
 halUsart! {
-    USART1: (usart1, apb2enr, usart1en, pclk2),
-    USART2: (usart2, apb1enr, usart2en, pclk1),
-    USART6: (usart6, apb2enr, usart6en, pclk2),
+    USART1: (usart1, APB2, usart1clk),
+    USART2: (usart2, APB1, usart2clk),
+    USART3: (usart3, APB1, usart3clk),
+    UART4: (uart4, APB1, uart4clk),
+    UART5: (uart5, APB1, uart5clk),
+    USART6: (usart6, APB2, usart6clk),
+    UART7: (uart7, APB1, uart7clk),
+    UART8: (uart8, APB1, uart8clk),
 }
 
-#[cfg(any(feature = "stm32f407", feature = "stm32f412", feature = "stm32f429"))]
-halUsart! {
-    USART3: (usart3, apb1enr, usart3en, pclk1),
+macro_rules! dma_targets {
+    ($(
+        $TARGET:ident<$USARTX:ident>: ($dr:ident, $DIR:ident, [$($STREAM:ty: $CHANNEL:ty),+]),
+    )+) => {
+        $(
+            $(
+                unsafe impl Target<$STREAM, $DIR> for $TARGET<$USARTX> {
+                    type Channel = $CHANNEL;
+                    type Word = u8;
+
+                    fn address(&self) -> u32 {
+                        // NOTE(unsafe) only the address of the register is taken
+                        unsafe { &(*$USARTX::ptr()).$dr as *const _ as u32 }
+                    }
+                }
+            )+
+        )+
+    }
+}
+
+dma_targets! {
+    Tx<USART1>: (tdr, MemoryToPeripheral, [dma2::S7: C4]),
+    Rx<USART1>: (rdr, PeripheralToMemory, [dma2::S2: C4, dma2::S5: C4]),
+    Tx<USART2>: (tdr, MemoryToPeripheral, [dma1::S6: C4]),
+    Rx<USART2>: (rdr, PeripheralToMemory, [dma1::S5: C4]),
+    Tx<USART3>: (tdr, MemoryToPeripheral, [dma1::S3: C4, dma1::S4: C7]),
+    Rx<USART3>: (rdr, PeripheralToMemory, [dma1::S1: C4]),
+    Tx<UART4>: (tdr, MemoryToPeripheral, [dma1::S4: C4]),
+    Rx<UART4>: (rdr, PeripheralToMemory, [dma1::S2: C4]),
+    Tx<UART5>: (tdr, MemoryToPeripheral, [dma1::S7: C4]),
+    Rx<UART5>: (rdr, PeripheralToMemory, [dma1::S0: C4]),
+    Tx<USART6>: (tdr, MemoryToPeripheral, [dma2::S6: C5, dma2::S7: C5]),
+    Rx<USART6>: (rdr, PeripheralToMemory, [dma2::S1: C5, dma2::S2: C5]),
+    Tx<UART7>: (tdr, MemoryToPeripheral, [dma1::S1: C5]),
+    Rx<UART7>: (rdr, PeripheralToMemory, [dma1::S3: C5]),
+    Tx<UART8>: (tdr, MemoryToPeripheral, [dma1::S0: C5]),
+    Rx<UART8>: (rdr, PeripheralToMemory, [dma1::S6: C5]),
 }
 
 impl<USART> fmt::Write for Tx<USART>
@@ -1,6 +1,8 @@
+use core::marker::PhantomData;
 use core::ptr;
 
 use hal;
+use hal::spi::FullDuplex;
 pub use hal::spi::{Mode, Phase, Polarity};
 use nb;
 
@@ -12,7 +14,7 @@ use nb;
     feature = "stm32f7x7",
     feature = "stm32f7x9",
 ))]
-use stm32::{RCC, SPI1, SPI2};
+use stm32::{SPI1, SPI2};
 
 #[cfg(any(
     feature = "stm32f7x2",
@@ -45,8 +47,6 @@ use stm32::SPI4;
 use stm32::SPI5;
 
 #[cfg(any(
-    feature = "stm32f7x2",
-    feature = "stm32f7x3",
     feature = "stm32f7x5",
     feature = "stm32f7x6",
     feature = "stm32f7x7",
@@ -62,118 +62,7 @@ use stm32::SPI6;
     feature = "stm32f7x7",
     feature = "stm32f7x9",
 ))]
-use gpio::gpioa::PA9;
-#[cfg(any(
-    feature = "stm32f7x2",
-    feature = "stm32f7x3",
-    feature = "stm32f7x5",
-    feature = "stm32f7x6",
-    feature = "stm32f7x7",
-    feature = "stm32f7x9",
-))]
-use gpio::gpioa::{PA1, PA11};
-#[cfg(any(
-    feature = "stm32f7x2",
-    feature = "stm32f7x3",
-    feature = "stm32f7x5",
-    feature = "stm32f7x6",
-    feature = "stm32f7x7",
-    feature = "stm32f7x9",
-))]
-use gpio::gpioa::{PA10, PA12};
-#[cfg(any(
-    feature = "stm32f7x2",
-    feature = "stm32f7x3",
-    feature = "stm32f7x5",
-    feature = "stm32f7x6",
-    feature = "stm32f7x7",
-    feature = "stm32f7x9",
-))]
-use gpio::gpioa::{PA5, PA6, PA7};
-
-#[cfg(any(
-    feature = "stm32f7x2",
-    feature = "stm32f7x3",
-    feature = "stm32f7x5",
-    feature = "stm32f7x6",
-    feature = "stm32f7x7",
-    feature = "stm32f7x9",
-))]
-use gpio::gpiob::PB0;
-#[cfg(any(
-    feature = "stm32f7x2",
-    feature = "stm32f7x3",
-    feature = "stm32f7x5",
-    feature = "stm32f7x6",
-    feature = "stm32f7x7",
-    feature = "stm32f7x9",
-))]
-use gpio::gpiob::PB12;
-#[cfg(any(
-    feature = "stm32f7x2",
-    feature = "stm32f7x3",
-    feature = "stm32f7x5",
-    feature = "stm32f7x6",
-    feature = "stm32f7x7",
-    feature = "stm32f7x9",
-))]
-use gpio::gpiob::PB2;
-#[cfg(any(
-    feature = "stm32f7x2",
-    feature = "stm32f7x3",
-    feature = "stm32f7x5",
-    feature = "stm32f7x6",
-    feature = "stm32f7x7",
-    feature = "stm32f7x9",
-))]
-use gpio::gpiob::PB8;
-#[cfg(any(
-    feature = "stm32f7x2",
-    feature = "stm32f7x3",
-    feature = "stm32f7x5",
-    feature = "stm32f7x6",
-    feature = "stm32f7x7",
-    feature = "stm32f7x9",
-))]
-use gpio::gpiob::{PB10, PB13, PB14, PB15, PB3, PB4, PB5};
-
-#[cfg(any(
-    feature = "stm32f7x2",
-    feature = "stm32f7x3",
-    feature = "stm32f7x5",
-    feature = "stm32f7x6",
-    feature = "stm32f7x7",
-    feature = "stm32f7x9",
-))]
-use gpio::gpioc::PC1;
-#[cfg(any(
-    feature = "stm32f7x2",
-    feature = "stm32f7x3",
-    feature = "stm32f7x5",
-    feature = "stm32f7x6",
-    feature = "stm32f7x7",
-    feature = "stm32f7x9",
-))]
-use gpio::gpioc::PC7;
-#[cfg(any(
-    feature = "stm32f7x2",
-    feature = "stm32f7x3",
-    feature = "stm32f7x5",
-    feature = "stm32f7x6",
-    feature = "stm32f7x7",
-    feature = "stm32f7x9",
-))]
-use gpio::gpioc::{PC10, PC11, PC12};
-#[cfg(any(
-    feature = "stm32f7x2",
-    feature = "stm32f7x3",
-    feature = "stm32f7x5",
-    feature = "stm32f7x6",
-    feature = "stm32f7x7",
-    feature = "stm32f7x9",
-))]
-use gpio::gpioc::{PC2, PC3};
-
+use gpio::gpioa::{PA1, PA10, PA11, PA12, PA15, PA4, PA5, PA6, PA7, PA9};
 #[cfg(any(
     feature = "stm32f7x2",
     feature = "stm32f7x3",
@@ -182,7 +71,7 @@ use gpio::gpioc::{PC2, PC3};
     feature = "stm32f7x7",
     feature = "stm32f7x9",
 ))]
-use gpio::gpiod::PD0;
+use gpio::gpiob::{PB10, PB12, PB13, PB14, PB15, PB2, PB3, PB4, PB5, PB9};
 #[cfg(any(
     feature = "stm32f7x2",
     feature = "stm32f7x3",
@@ -191,8 +80,7 @@ use gpio::gpiod::PD0;
     feature = "stm32f7x7",
     feature = "stm32f7x9",
 ))]
-use gpio::gpiod::{PD3, PD6};
-
+use gpio::gpioc::{PC1, PC10, PC11, PC12, PC2, PC3};
 #[cfg(any(
     feature = "stm32f7x2",
     feature = "stm32f7x3",
@@ -201,8 +89,7 @@ use gpio::gpiod::{PD3, PD6};
     feature = "stm32f7x7",
     feature = "stm32f7x9",
 ))]
-use gpio::gpioe::{PE12, PE13, PE14, PE2, PE5, PE6};
-
+use gpio::gpiod::{PD3, PD6, PD7};
 #[cfg(any(
     feature = "stm32f7x2",
     feature = "stm32f7x3",
@@ -211,8 +98,7 @@ use gpio::gpioe::{PE12, PE13, PE14, PE2, PE5, PE6};
     feature = "stm32f7x7",
     feature = "stm32f7x9",
 ))]
-use gpio::gpiof::{PF11, PF7, PF8, PF9};
-
+use gpio::gpioe::{PE11, PE12, PE13, PE14, PE2, PE4, PE5, PE6};
 #[cfg(any(
     feature = "stm32f7x2",
     feature = "stm32f7x3",
@@ -221,16 +107,14 @@ use gpio::gpiof::{PF11, PF7, PF8, PF9};
     feature = "stm32f7x7",
     feature = "stm32f7x9",
 ))]
-use gpio::gpiog::PG11;
+use gpio::gpiof::{PF11, PF6, PF7, PF8, PF9};
 #[cfg(any(
-    feature = "stm32f7x2",
-    feature = "stm32f7x3",
     feature = "stm32f7x5",
     feature = "stm32f7x6",
     feature = "stm32f7x7",
     feature = "stm32f7x9",
 ))]
-use gpio::gpiog::PG14;
+use gpio::gpiog::{PG12, PG13, PG14, PG8};
 #[cfg(any(
     feature = "stm32f7x2",
     feature = "stm32f7x3",
@@ -239,8 +123,7 @@ use gpio::gpiog::PG14;
     feature = "stm32f7x7",
     feature = "stm32f7x9",
 ))]
-use gpio::gpiog::{PG12, PG13};
-
+use gpio::gpioh::{PH5, PH6, PH7};
 #[cfg(any(
     feature = "stm32f7x2",
     feature = "stm32f7x3",
@@ -249,7 +132,7 @@ use gpio::gpiog::{PG12, PG13};
     feature = "stm32f7x7",
     feature = "stm32f7x9",
 ))]
-use gpio::gpioh::{PH6, PH7};
+use gpio::gpioi::{PI0, PI1, PI2, PI3};
 
 #[cfg(any(
     feature = "stm32f7x2",
@@ -259,28 +142,21 @@ use gpio::gpioh::{PH6, PH7};
     feature = "stm32f7x7",
     feature = "stm32f7x9",
 ))]
-use gpio::gpioi::{PI1, PI2, PI3};
+use gpio::{Alternate, AF5, AF6, AF7};
 
+use self::config::{BitOrder, Config, Direction, InvalidConfig};
 #[cfg(any(
-    feature = "stm32f7x2",
-    feature = "stm32f7x3",
-    feature = "stm32f7x5",
-    feature = "stm32f7x6",
-    feature = "stm32f7x7",
-    feature = "stm32f7x9",
-))]
-use gpio::AF7;
-#[cfg(any(
-    feature = "stm32f7x2",
-    feature = "stm32f7x3",
     feature = "stm32f7x5",
     feature = "stm32f7x6",
     feature = "stm32f7x7",
     feature = "stm32f7x9",
 ))]
-use gpio::{Alternate, AF5, AF6};
-
-use rcc::Clocks;
+use dma::C1;
+use dma::{self, dma1, dma2, C0, C2, C3, C4, C5, C7};
+use dma::{
+    MemoryToPeripheral, PeripheralToMemory, ReadBuffer, Stream, Target, Transfer, WriteBuffer,
+};
+use rcc::{Clocks, Enable, APB1, APB2};
 use time::Hertz;
 
 /// SPI error
@@ -292,14 +168,116 @@ pub enum Error {
     ModeFault,
     /// CRC error
     Crc,
+    /// DMA transfer error
+    Dma(dma::Error),
     #[doc(hidden)]
     _Extensible,
 }
 
+/// Interrupt event
+pub enum Event {
+    /// New data has been received
+    Rxne,
+    /// New data can be sent
+    Txe,
+    /// An overrun, mode fault, CRC or frame format error occurred
+    Error,
+}
+
+pub mod config {
+    use hal::spi::{Mode, MODE_0};
+
+    #[derive(PartialEq)]
+    pub enum BitOrder {
+        MsbFirst,
+        LsbFirst,
+    }
+
+    #[derive(PartialEq)]
+    pub enum Direction {
+        /// Separate lines for sending and receiving
+        FullDuplex,
+        /// A single data line switched with `Spi::set_bidi_output` (3-wire half duplex)
+        Bidirectional,
+        /// Receiving only; a master starts generating the clock with the first read and keeps
+        /// clocking in frames from then on
+        RxOnly,
+    }
+
+    pub struct Config {
+        pub mode: Mode,
+        pub bit_order: BitOrder,
+        pub direction: Direction,
+        pub crc_polynomial: Option<u16>,
+    }
+
+    impl Config {
+        pub fn mode(mut self, mode: Mode) -> Self {
+            self.mode = mode;
+            self
+        }
+
+        pub fn msb_first(mut self) -> Self {
+            self.bit_order = BitOrder::MsbFirst;
+            self
+        }
+
+        pub fn lsb_first(mut self) -> Self {
+            self.bit_order = BitOrder::LsbFirst;
+            self
+        }
+
+        pub fn full_duplex(mut self) -> Self {
+            self.direction = Direction::FullDuplex;
+            self
+        }
+
+        pub fn bidirectional(mut self) -> Self {
+            self.direction = Direction::Bidirectional;
+            self
+        }
+
+        pub fn rx_only(mut self) -> Self {
+            self.direction = Direction::RxOnly;
+            self
+        }
+
+        /// Enables the hardware CRC with the given polynomial, which has to be odd
+        pub fn crc(mut self, polynomial: u16) -> Self {
+            self.crc_polynomial = Some(polynomial);
+            self
+        }
+    }
+
+    impl Default for Config {
+        fn default() -> Config {
+            Config {
+                mode: MODE_0,
+                bit_order: BitOrder::MsbFirst,
+                direction: Direction::FullDuplex,
+                crc_polynomial: None,
+            }
+        }
+    }
+
+    impl From<Mode> for Config {
+        fn from(mode: Mode) -> Config {
+            Config::default().mode(mode)
+        }
+    }
+
+    /// The requested frequency is below the lowest one the SPI can generate, the CRC polynomial
+    /// is even or the frame size doesn't fit the word type
+    #[derive(Debug)]
+    pub struct InvalidConfig;
+}
+
 pub trait Pins<SPI> {}
+pub trait SlavePins<SPI> {}
 pub trait PinSck<SPI> {}
 pub trait PinMiso<SPI> {}
 pub trait PinMosi<SPI> {}
+pub trait PinNss<SPI> {}
 
 impl<SPI, SCK, MISO, MOSI> Pins<SPI> for (SCK, MISO, MOSI)
 where
@@ -309,6 +287,15 @@ where
 {
 }
 
+impl<SPI, SCK, MISO, MOSI, NSS> SlavePins<SPI> for (SCK, MISO, MOSI, NSS)
+where
+    SCK: PinSck<SPI>,
+    MISO: PinMiso<SPI>,
+    MOSI: PinMosi<SPI>,
+    NSS: PinNss<SPI>,
+{
+}
+
 /// A filler type for when the SCK pin is unnecessary
 pub struct NoSck;
 /// A filler type for when the Miso pin is unnecessary
@@ -317,7 +304,12 @@ pub struct NoMiso;
 pub struct NoMosi;
 
 macro_rules! pins {
-    ($($SPIX:ty: SCK: [$($SCK:ty),*] MISO: [$($MISO:ty),*] MOSI: [$($MOSI:ty),*])+) => {
+    ($($SPIX:ty:
+        SCK: [$($SCK:ty),*]
+        MISO: [$($MISO:ty),*]
+        MOSI: [$($MOSI:ty),*]
+        NSS: [$($NSS:ty),*]
+    )+) => {
         $(
             $(
                 impl PinSck<$SPIX> for $SCK {}
@@ -328,171 +320,13 @@ macro_rules! pins {
             $(
                 impl PinMosi<$SPIX> for $MOSI {}
             )*
+            $(
+                impl PinNss<$SPIX> for $NSS {}
+            )*
         )+
     }
 }
 
-// #[cfg(any(
-//     feature = "stm32f7x2",
-//     feature = "stm32f7x3",
-//     feature = "stm32f7x5",
-//     feature = "stm32f7x6",
-//     feature = "stm32f7x7",
-//     feature = "stm32f7x9",
-// ))]
-// pins! {
-//     SPI1:
-//         SCK: [
-//             NoSck,
-//             PA5<Alternate<AF5>>,
-//             PB3<Alternate<AF5>>
-//         ]
-//         MISO: [
-//             NoMiso,
-//             PA6<Alternate<AF5>>,
-//             PB4<Alternate<AF5>>
-//         ]
-//         MOSI: [
-//             NoMosi,
-//             PA7<Alternate<AF5>>,
-//             PB5<Alternate<AF5>>
-//         ]
-
-//     SPI2:
-//         SCK: [
-//             NoSck,
-//             PB10<Alternate<AF5>>,
-//             PB13<Alternate<AF5>>
-//         ]
-//         MISO: [
-//             NoMiso,
-//             PB14<Alternate<AF5>>,
-//             PC2<Alternate<AF5>>
-//         ]
-//         MOSI: [
-//             NoMosi,
-//             PB15<Alternate<AF5>>,
-//             PC3<Alternate<AF5>>
-//         ]
-// }
-
-// #[cfg(any(
-//     feature = "stm32f7x2",
-//     feature = "stm32f7x3",
-//     feature = "stm32f7x5",
-//     feature = "stm32f7x6",
-//     feature = "stm32f7x7",
-//     feature = "stm32f7x9",
-// ))]
-// pins! {
-//     SPI3:
-//         SCK: [
-//             NoSck,
-//             PB3<Alternate<AF6>>,
-//             PC10<Alternate<AF6>>
-//         ]
-//         MISO: [
-//             NoMiso,
-//             PB4<Alternate<AF6>>,
-//             PC11<Alternate<AF6>>
-//         ]
-//         MOSI: [
-//             NoMosi,
-//             PB5<Alternate<AF6>>,
-//             PC12<Alternate<AF6>>
-//         ]
-// }
-
-// #[cfg(any(
-//     feature = "stm32f7x2",
-//     feature = "stm32f7x3",
-//     feature = "stm32f7x5",
-//     feature = "stm32f7x6",
-//     feature = "stm32f7x7",
-//     feature = "stm32f7x9",
-// ))]
-// pins! {
-//     SPI2:
-//         SCK: [PD3<Alternate<AF5>>]
-//         MISO: []
-//         MOSI: []
-//     SPI3:
-//         SCK: []
-//         MISO: []
-//         MOSI: [PD6<Alternate<AF5>>]
-//     SPI4:
-//         SCK: [
-//             NoSck,
-//             PE2<Alternate<AF5>>,
-//             PE12<Alternate<AF5>>
-//         ]
-//         MISO: [
-//             NoMiso,
-//             PE5<Alternate<AF5>>,
-//             PE13<Alternate<AF5>>
-//         ]
-//         MOSI: [
-//             NoMosi,
-//             PE6<Alternate<AF5>>,
-//             PE14<Alternate<AF5>>
-//         ]
-// }
-
-// #[cfg(any(
-//     feature = "stm32f7x2",
-//     feature = "stm32f7x3",
-//     feature = "stm32f7x5",
-//     feature = "stm32f7x6",
-//     feature = "stm32f7x7",
-//     feature = "stm32f7x9",
-// ))]
-// pins! {
-//     SPI2:
-//         SCK: [PI1<Alternate<AF5>>]
-//         MISO: [PI2<Alternate<AF5>>]
-//         MOSI: [PI3<Alternate<AF5>>]
-// }
-
-// #[cfg(any(
-//     feature = "stm32f7x2",
-//     feature = "stm32f7x3",
-//     feature = "stm32f7x5",
-//     feature = "stm32f7x6",
-//     feature = "stm32f7x7",
-//     feature = "stm32f7x9",
-// ))]
-// pins! {
-//     SPI2:
-//         SCK: [PC7<Alternate<AF5>>]
-//         MISO: []
-//         MOSI: []
-// }
-
-// #[cfg(any(
-//     feature = "stm32f7x2",
-//     feature = "stm32f7x3",
-//     feature = "stm32f7x5",
-//     feature = "stm32f7x6",
-//     feature = "stm32f7x7",
-//     feature = "stm32f7x9",
-// ))]
-// pins! {
-//     SPI5:
-//         SCK: [
-//             NoSck,
-//             PB0<Alternate<AF6>>
-//         ]
-//         MISO: [
-//             NoMiso,
-//             PA12<Alternate<AF6>>
-//         ]
-//         MOSI: [
-//             NoMosi,
-//             PA10<Alternate<AF6>>,
-//             PB8<Alternate<AF6>>
-//         ]
-// }
-
 #[cfg(any(
     feature = "stm32f7x2",
     feature = "stm32f7x3",
@@ -502,235 +336,821 @@ macro_rules! pins {
     feature = "stm32f7x9",
 ))]
 pins! {
+    SPI1:
+        SCK: [
+            NoSck,
+            PA5<Alternate<AF5>>,
+            PB3<Alternate<AF5>>
+        ]
+        MISO: [
+            NoMiso,
+            PA6<Alternate<AF5>>,
+            PB4<Alternate<AF5>>
+        ]
+        MOSI: [
+            NoMosi,
+            PA7<Alternate<AF5>>,
+            PB5<Alternate<AF5>>,
+            PD7<Alternate<AF5>>
+        ]
+        NSS: [
+            PA4<Alternate<AF5>>,
+            PA15<Alternate<AF5>>
+        ]
+
+    SPI2:
+        SCK: [
+            NoSck,
+            PA9<Alternate<AF5>>,
+            PB10<Alternate<AF5>>,
+            PB13<Alternate<AF5>>,
+            PD3<Alternate<AF5>>,
+            PI1<Alternate<AF5>>
+        ]
+        MISO: [
+            NoMiso,
+            PA12<Alternate<AF5>>,
+            PB14<Alternate<AF5>>,
+            PC2<Alternate<AF5>>,
+            PI2<Alternate<AF5>>
+        ]
+        MOSI: [
+            NoMosi,
+            PA10<Alternate<AF5>>,
+            PB15<Alternate<AF5>>,
+            PC1<Alternate<AF5>>,
+            PC3<Alternate<AF5>>,
+            PI3<Alternate<AF5>>
+        ]
+        NSS: [
+            PB4<Alternate<AF7>>,
+            PB9<Alternate<AF5>>,
+            PB12<Alternate<AF5>>,
+            PI0<Alternate<AF5>>
+        ]
+
     SPI3:
-        SCK: [PB12<Alternate<AF7>>]
-        MISO: []
-        MOSI: []
+        SCK: [
+            NoSck,
+            PB3<Alternate<AF6>>,
+            PB12<Alternate<AF7>>,
+            PC10<Alternate<AF6>>
+        ]
+        MISO: [
+            NoMiso,
+            PB4<Alternate<AF6>>,
+            PC11<Alternate<AF6>>
+        ]
+        MOSI: [
+            NoMosi,
+            PB2<Alternate<AF7>>,
+            PB5<Alternate<AF6>>,
+            PC12<Alternate<AF6>>,
+            PD6<Alternate<AF5>>
+        ]
+        NSS: [
+            PA4<Alternate<AF6>>,
+            PA15<Alternate<AF6>>
+        ]
+
     SPI4:
-        SCK: [PB13<Alternate<AF6>>]
-        MISO: [PA11<Alternate<AF6>>]
-        MOSI: [PA1<Alternate<AF5>>]
+        SCK: [
+            NoSck,
+            PB13<Alternate<AF6>>,
+            PE2<Alternate<AF5>>,
+            PE12<Alternate<AF5>>
+        ]
+        MISO: [
+            NoMiso,
+            PA11<Alternate<AF6>>,
+            PE5<Alternate<AF5>>,
+            PE13<Alternate<AF5>>
+        ]
+        MOSI: [
+            NoMosi,
+            PA1<Alternate<AF5>>,
+            PE6<Alternate<AF5>>,
+            PE14<Alternate<AF5>>
+        ]
+        NSS: [
+            PE4<Alternate<AF5>>,
+            PE11<Alternate<AF5>>
+        ]
+
     SPI5:
         SCK: [
+            NoSck,
             PE2<Alternate<AF6>>,
-            PE12<Alternate<AF6>>
+            PE12<Alternate<AF6>>,
+            PF7<Alternate<AF5>>,
+            PH6<Alternate<AF5>>
         ]
         MISO: [
+            NoMiso,
             PE5<Alternate<AF6>>,
-            PE13<Alternate<AF6>>
+            PE13<Alternate<AF6>>,
+            PF8<Alternate<AF5>>,
+            PH7<Alternate<AF5>>
         ]
         MOSI: [
+            NoMosi,
             PE6<Alternate<AF6>>,
-            PE14<Alternate<AF6>>
+            PE14<Alternate<AF6>>,
+            PF9<Alternate<AF5>>,
+            PF11<Alternate<AF5>>
+        ]
+        NSS: [
+            PF6<Alternate<AF5>>,
+            PH5<Alternate<AF5>>
         ]
 }
 
+#[cfg(any(feature = "stm32f7x2", feature = "stm32f7x3"))]
+pins! {
+    SPI2:
+        SCK: []
+        MISO: []
+        MOSI: []
+        NSS: [PA11<Alternate<AF5>>]
+}
+
 #[cfg(any(
-    feature = "stm32f7x2",
-    feature = "stm32f7x3",
     feature = "stm32f7x5",
     feature = "stm32f7x6",
     feature = "stm32f7x7",
     feature = "stm32f7x9",
 ))]
 pins! {
-    SPI2:
-        SCK: [PA9<Alternate<AF5>>]
-        MISO: [PA12<Alternate<AF5>>]
-        MOSI: [PA10<Alternate<AF5>>]
+    SPI6:
+        SCK: [
+            NoSck,
+            PG13<Alternate<AF5>>
+        ]
+        MISO: [
+            NoMiso,
+            PG12<Alternate<AF5>>
+        ]
+        MOSI: [
+            NoMosi,
+            PG14<Alternate<AF5>>
+        ]
+        NSS: [PG8<Alternate<AF5>>]
+}
+
+/// Data word holding a frame
+///
+/// Frames of 4 to 8 bits are transferred as `u8`, frames of 9 to 16 bits as `u16`.
+pub trait Word: dma::Word + Copy {
+    /// Smallest frame size in bits
+    const MIN_SIZE: u8;
+    /// Largest frame size in bits
+    const MAX_SIZE: u8;
 }
 
-// #[cfg(any(
-//     feature = "stm32f7x2",
-//     feature = "stm32f7x3",
-//     feature = "stm32f7x5",
-//     feature = "stm32f7x6",
-//     feature = "stm32f7x7",
-//     feature = "stm32f7x9",
-// ))]
-// pins! {
-//     SPI5:
-//         SCK: [
-//             NoSck,
-//             PF7<Alternate<AF5>>,
-//             PH6<Alternate<AF5>>
-//         ]
-//         MISO: [
-//             NoMiso,
-//             PF8<Alternate<AF5>>,
-//             PH7<Alternate<AF5>>
-//         ]
-//         MOSI: [
-//             NoMosi,
-//             PF9<Alternate<AF5>>,
-//             PF11<Alternate<AF5>>
-//         ]
-
-//     SPI6:
-//         SCK: [
-//             NoSck,
-//             PG13<Alternate<AF5>>
-//         ]
-//         MISO: [
-//             NoMiso,
-//             PG12<Alternate<AF5>>
-//         ]
-//         MOSI: [
-//             NoMosi,
-//             PG14<Alternate<AF5>>
-//         ]
-// }
-
-// #[cfg(any(
-//     feature = "stm32f7x2",
-//     feature = "stm32f7x3",
-//     feature = "stm32f7x5",
-//     feature = "stm32f7x6",
-//     feature = "stm32f7x7",
-//     feature = "stm32f7x9",
-// ))]
-// pins! {
-//     SPI2:
-//         SCK: [PA9<Alternate<AF5>>]
-//         MISO: []
-//         MOSI: [PC1<Alternate<AF7>>]
-
-//     SPI3:
-//         SCK: []
-//         MISO: []
-//         MOSI: [
-//             PB0<Alternate<AF7>>,
-//             PB2<Alternate<AF7>>,
-//             PD0<Alternate<AF6>>
-//         ]
-
-//     SPI4:
-//         SCK: [PG11<Alternate<AF6>>]
-//         MISO: [
-//             PG12<Alternate<AF6>>,
-//             PD0<Alternate<AF5>>
-//         ]
-//         MOSI: [PG13<Alternate<AF6>>]
-// }
-
-// #[cfg(any(
-//     feature = "stm32f7x2",
-//     feature = "stm32f7x3",
-//     feature = "stm32f7x5",
-//     feature = "stm32f7x6",
-//     feature = "stm32f7x7",
-//     feature = "stm32f7x9",
-// ))]
-// pins! {
-//     SPI2:
-//         SCK: [PA9<Alternate<AF5>>]
-//         MISO: []
-//         MOSI: [PC1<Alternate<AF5>>]
-// }
+impl Word for u8 {
+    const MIN_SIZE: u8 = 4;
+    const MAX_SIZE: u8 = 8;
+}
+
+impl Word for u16 {
+    const MIN_SIZE: u8 = 9;
+    const MAX_SIZE: u8 = 16;
+}
 
 #[derive(Debug)]
-pub struct Spi<SPI, PINS> {
+pub struct Spi<SPI, PINS, WORD = u8> {
     spi: SPI,
     pins: PINS,
+    frequency: Hertz,
+    _word: PhantomData<WORD>,
+}
+
+/// The transmit DMA request of an SPI
+pub struct Tx<SPI, WORD> {
+    _spi: PhantomData<(SPI, WORD)>,
+}
+
+/// The receive DMA request of an SPI
+pub struct Rx<SPI, WORD> {
+    _spi: PhantomData<(SPI, WORD)>,
+}
+
+/// An ongoing full duplex DMA transfer, see `Spi::transfer_dma`
+pub struct DmaTransfer<SPI, PINS, WORD, TXSTREAM, RXSTREAM, TXBUFFER, RXBUFFER>
+where
+    TXSTREAM: Stream,
+    RXSTREAM: Stream,
+{
+    spi: Spi<SPI, PINS, WORD>,
+    tx: Transfer<TXSTREAM, Tx<SPI, WORD>, TXBUFFER, MemoryToPeripheral>,
+    rx: Transfer<RXSTREAM, Rx<SPI, WORD>, RXBUFFER, PeripheralToMemory>,
+}
+
+/// An ongoing transmit only DMA transfer, see `Spi::write_dma`
+pub struct DmaWrite<SPI, PINS, WORD, STREAM, BUFFER>
+where
+    STREAM: Stream,
+{
+    spi: Spi<SPI, PINS, WORD>,
+    tx: Transfer<STREAM, Tx<SPI, WORD>, BUFFER, MemoryToPeripheral>,
 }
 
+/// An ongoing transfer driven by the SPI interrupt, see `Spi::transfer_interrupt`
+pub struct InterruptTransfer<SPI, PINS, WORD, BUFFER> {
+    spi: Spi<SPI, PINS, WORD>,
+    buffer: BUFFER,
+    len: usize,
+    sent: usize,
+    received: usize,
+}
+
+/// Number of frames sent ahead of the received ones, which fit in either FIFO
+const FRAMES_IN_FLIGHT: usize = 2;
+
 macro_rules! hal {
-    ($($SPIX:ident: ($spiX:ident, $apbXenr:ident, $spiXen:ident, $pclkX:ident),)+) => {
+    ($($SPIX:ident: ($spiX:ident, $spiX_slave:ident, $APBX:ident, $pclkX:ident),)+) => {
         $(
             impl<PINS> Spi<$SPIX, PINS> {
-                pub fn $spiX(
+                pub fn $spiX<C>(
                     spi: $SPIX,
                     pins: PINS,
-                    mode: Mode,
+                    config: C,
                     freq: Hertz,
-                    clocks: Clocks
-                ) -> Self
-                where PINS: Pins<$SPIX> {
-                    // NOTE(unsafe) This executes only during initialisation
-                    let rcc = unsafe { &(*RCC::ptr()) };
-
+                    clocks: Clocks,
+                    apb: &mut $APBX,
+                ) -> Result<Self, InvalidConfig>
+                where
+                    PINS: Pins<$SPIX>,
+                    C: Into<Config>,
+                {
                     // Enable clock for SPI
-                    rcc.$apbXenr.modify(|_, w| w.$spiXen().set_bit());
-
-                    // disable SS output
-                    spi.cr2.write(|w| w.ssoe().clear_bit());
-
-                    let br = match clocks.$pclkX().0 / freq.0 {
-                        0 => unreachable!(),
-                        1...2 => 0b000,
-                        3...5 => 0b001,
-                        6...11 => 0b010,
-                        12...23 => 0b011,
-                        24...47 => 0b100,
-                        48...95 => 0b101,
-                        96...191 => 0b110,
-                        _ => 0b111,
+                    $SPIX::enable(apb);
+
+                    // The smallest divider that doesn't exceed the requested frequency
+                    let pclk = clocks.$pclkX().0;
+                    let br = match (0..8).find(|br| pclk >> (br + 1) <= freq.0) {
+                        Some(br) => br,
+                        None => return Err(InvalidConfig),
                     };
 
-                    // mstr: master configuration
-                    // lsbfirst: MSB first
-                    // ssm: enable software slave management (NSS pin free for other uses)
+                    Self::init(
+                        spi,
+                        pins,
+                        config.into(),
+                        Some((Hertz(pclk >> (br + 1)), br as u8)),
+                    )
+                }
+
+                /// Configures the SPI as a slave selected through the hardware NSS pin
+                ///
+                /// The host drives the clock, and frames are only exchanged while it holds NSS
+                /// low. The first frame to send has to be written before the host starts clocking,
+                /// either with `send`, in the `Txe` interrupt or by starting a DMA transfer.
+                pub fn $spiX_slave<C>(
+                    spi: $SPIX,
+                    pins: PINS,
+                    config: C,
+                    apb: &mut $APBX,
+                ) -> Result<Self, InvalidConfig>
+                where
+                    PINS: SlavePins<$SPIX>,
+                    C: Into<Config>,
+                {
+                    // Enable clock for SPI
+                    $SPIX::enable(apb);
+
+                    Self::init(spi, pins, config.into(), None)
+                }
+
+                /// Configures a master generating SCK at the frequency and with the `BR` bits
+                /// given by `sck`, or a slave without `sck`
+                ///
+                /// Slaves report a frequency of 0, their clock is up to the host.
+                fn init(
+                    spi: $SPIX,
+                    pins: PINS,
+                    config: Config,
+                    sck: Option<(Hertz, u8)>,
+                ) -> Result<Self, InvalidConfig> {
+                    let master = sck.is_some();
+                    let (frequency, br) = sck.unwrap_or((Hertz(0), 0));
+
+                    // The polynomial has to be odd, otherwise the CRC isn't defined
+                    if config.crc_polynomial.map_or(false, |polynomial| polynomial & 1 == 0) {
+                        return Err(InvalidConfig);
+                    }
+
+                    // ssoe: disable SS output
+                    // ds: 8 bit frames
+                    // frxth: RXNE as soon as a byte has been received
+                    spi.cr2.write(|w| unsafe {
+                        w.ssoe().clear_bit().ds().bits(8 - 1).frxth().set_bit()
+                    });
+
+                    if let Some(polynomial) = config.crc_polynomial {
+                        spi.crcpr.write(|w| w.crcpoly().bits(polynomial));
+                    }
+
+                    // mstr: master or slave configuration
+                    // ssm: masters use software slave management (NSS pin free for other uses),
+                    //      slaves are selected by the NSS pin
                     // ssi: set nss high = master mode
-                    // dff: 8 bit frames
-                    // bidimode: 2-line unidirectional
-                    // spe: enable the SPI bus
+                    // crcl: 8 bit CRC, matching the frame size
+                    // bidioe: 3-wire masters start out transmitting, slaves receiving
+                    // spe: enable the SPI bus, except for receive only masters which would start
+                    //      clocking in frames right away, see `start_rx_only`
                     spi.cr1.write(|w| {
                         w.cpha()
-                            .bit(mode.phase == Phase::CaptureOnSecondTransition)
+                            .bit(config.mode.phase == Phase::CaptureOnSecondTransition)
                             .cpol()
-                            .bit(mode.polarity == Polarity::IdleHigh)
+                            .bit(config.mode.polarity == Polarity::IdleHigh)
                             .mstr()
-                            .set_bit()
+                            .bit(master)
                             .br()
                             .bits(br)
                             .lsbfirst()
-                            .clear_bit()
+                            .bit(config.bit_order == BitOrder::LsbFirst)
                             .ssm()
-                            .set_bit()
+                            .bit(master)
                             .ssi()
-                            .set_bit()
-                            .rxonly()
-                            .clear_bit()
-                            .dff()
+                            .bit(master)
+                            .crcen()
+                            .bit(config.crc_polynomial.is_some())
+                            .crcl()
                             .clear_bit()
+                            .rxonly()
+                            .bit(config.direction == Direction::RxOnly)
                             .bidimode()
-                            .clear_bit()
+                            .bit(config.direction == Direction::Bidirectional)
+                            .bidioe()
+                            .bit(master)
                             .spe()
-                            .set_bit()
+                            .bit(!master || config.direction != Direction::RxOnly)
                     });
 
-                    Spi { spi, pins }
+                    Ok(Spi {
+                        spi,
+                        pins,
+                        frequency,
+                        _word: PhantomData,
+                    })
+                }
+            }
+
+            impl<PINS, WORD> Spi<$SPIX, PINS, WORD>
+            where
+                WORD: Word,
+            {
+                /// Starts listening for an interrupt event
+                pub fn listen(&mut self, event: Event) {
+                    match event {
+                        Event::Rxne => self.spi.cr2.modify(|_, w| w.rxneie().set_bit()),
+                        Event::Txe => self.spi.cr2.modify(|_, w| w.txeie().set_bit()),
+                        Event::Error => self.spi.cr2.modify(|_, w| w.errie().set_bit()),
+                    }
+                }
+
+                /// Stops listening for an interrupt event
+                pub fn unlisten(&mut self, event: Event) {
+                    match event {
+                        Event::Rxne => self.spi.cr2.modify(|_, w| w.rxneie().clear_bit()),
+                        Event::Txe => self.spi.cr2.modify(|_, w| w.txeie().clear_bit()),
+                        Event::Error => self.spi.cr2.modify(|_, w| w.errie().clear_bit()),
+                    }
                 }
 
                 pub fn free(self) -> ($SPIX, PINS) {
                     (self.spi, self.pins)
                 }
-            }
 
-            impl<PINS> hal::spi::FullDuplex<u8> for Spi<$SPIX, PINS> {
-                type Error = Error;
+                /// Returns the SCK frequency, which is 0 for slaves
+                pub fn actual_frequency(&self) -> Hertz {
+                    self.frequency
+                }
 
-                fn read(&mut self) -> nb::Result<u8, Error> {
+                /// Changes the frame size to `bits`, transferring the frames as `W`
+                ///
+                /// Fails unless `bits` is between 4 and 8 for `u8` or between 9 and 16 for `u16`.
+                pub fn frame_size<W>(self, bits: u8) -> Result<Spi<$SPIX, PINS, W>, InvalidConfig>
+                where
+                    W: Word,
+                {
+                    if bits < W::MIN_SIZE || bits > W::MAX_SIZE {
+                        return Err(InvalidConfig);
+                    }
+
+                    // The frame format can only be changed while the SPI is disabled
+                    let enabled = self.spi.cr1.read().spe().bit_is_set();
+                    self.spi.cr1.modify(|_, w| w.spe().clear_bit());
+                    self.spi.cr2.modify(|_, w| unsafe {
+                        w.ds().bits(bits - 1).frxth().bit(W::MAX_SIZE <= 8)
+                    });
+                    self.spi
+                        .cr1
+                        .modify(|_, w| w.crcl().bit(W::MAX_SIZE > 8).spe().bit(enabled));
+
+                    Ok(Spi {
+                        spi: self.spi,
+                        pins: self.pins,
+                        frequency: self.frequency,
+                        _word: PhantomData,
+                    })
+                }
+
+                /// Selects whether a 3-wire half duplex SPI drives the data line
+                ///
+                /// Note that a master generates the clock for as long as it is receiving.
+                pub fn set_bidi_output(&mut self, output: bool) {
+                    self.spi.cr1.modify(|_, w| w.bidioe().bit(output));
+                }
+
+                /// Sends the CRC after the frame currently being sent
+                ///
+                /// Has to be called between writing the last frame and the end of its
+                /// transmission. DMA transfers send the CRC on their own.
+                pub fn send_crc(&mut self) {
+                    self.spi.cr1.modify(|_, w| w.crcnext().set_bit());
+                }
+
+                /// Checks the CRC received after the last frame and restarts the CRC calculation
+                pub fn check_crc(&mut self) -> Result<(), Error> {
+                    let error = self.spi.sr.read().crcerr().bit_is_set();
+                    self.spi.sr.modify(|_, w| w.crcerr().clear_bit());
+
+                    // The CRC is reset by disabling the calculation, which requires a disabled SPI.
+                    // Let the last frame complete first, except in receive only mode where a
+                    // master keeps clocking for as long as it is enabled.
+                    let cr1 = self.spi.cr1.read();
+                    let enabled = cr1.spe().bit_is_set();
+                    if cr1.rxonly().bit_is_clear() {
+                        while !self.is_idle() {}
+                    }
+                    self.spi.cr1.modify(|_, w| w.spe().clear_bit());
+                    self.spi.cr1.modify(|_, w| w.crcen().clear_bit());
+                    self.spi
+                        .cr1
+                        .modify(|_, w| w.crcen().set_bit().spe().bit(enabled));
+
+                    if error {
+                        Err(Error::Crc)
+                    } else {
+                        Ok(())
+                    }
+                }
+
+                /// Starts sending `tx_buffer` while receiving into `rx_buffer` using DMA
+                ///
+                /// Chip select is up to the caller: assert it before starting the transfer and
+                /// release it once the returned transfer is complete.
+                ///
+                /// Fails with `dma::Error::BufferLength` if the buffers differ in length, are empty
+                /// or are too long, returning the resources.
+                #[allow(clippy::type_complexity)]
+                pub fn transfer_dma<TXSTREAM, RXSTREAM, TXBUFFER, RXBUFFER>(
+                    self,
+                    tx_buffer: TXBUFFER,
+                    mut rx_buffer: RXBUFFER,
+                    tx_stream: TXSTREAM,
+                    rx_stream: RXSTREAM,
+                ) -> Result<
+                    DmaTransfer<$SPIX, PINS, WORD, TXSTREAM, RXSTREAM, TXBUFFER, RXBUFFER>,
+                    (Error, Self, (TXSTREAM, TXBUFFER), (RXSTREAM, RXBUFFER)),
+                >
+                where
+                    TXSTREAM: Stream,
+                    RXSTREAM: Stream,
+                    Tx<$SPIX, WORD>: Target<TXSTREAM, MemoryToPeripheral, Word = WORD>,
+                    Rx<$SPIX, WORD>: Target<RXSTREAM, PeripheralToMemory, Word = WORD>,
+                    TXBUFFER: ReadBuffer<Word = WORD>,
+                    RXBUFFER: WriteBuffer<Word = WORD>,
+                {
+                    if tx_buffer.read_buffer().1 != rx_buffer.write_buffer().1 {
+                        return Err((
+                            Error::Dma(dma::Error::BufferLength),
+                            self,
+                            (tx_stream, tx_buffer),
+                            (rx_stream, rx_buffer),
+                        ));
+                    }
+
+                    // The receive stream has to be ready before the first frame is sent
+                    self.spi.cr2.modify(|_, w| w.rxdmaen().set_bit());
+                    let rx = match Transfer::read(
+                        rx_stream,
+                        Rx { _spi: PhantomData },
+                        rx_buffer,
+                        dma::Config::default(),
+                    ) {
+                        Ok(rx) => rx,
+                        Err((error, rx_stream, _, rx_buffer)) => {
+                            self.spi.cr2.modify(|_, w| w.rxdmaen().clear_bit());
+                            return Err((
+                                Error::Dma(error),
+                                self,
+                                (tx_stream, tx_buffer),
+                                (rx_stream, rx_buffer),
+                            ));
+                        }
+                    };
+                    let tx = match Transfer::write(
+                        tx_stream,
+                        Tx { _spi: PhantomData },
+                        tx_buffer,
+                        dma::Config::default(),
+                    ) {
+                        Ok(tx) => tx,
+                        Err((error, tx_stream, _, tx_buffer)) => {
+                            let (rx_stream, _, rx_buffer, _) = rx.abort();
+                            self.spi.cr2.modify(|_, w| w.rxdmaen().clear_bit());
+                            return Err((
+                                Error::Dma(error),
+                                self,
+                                (tx_stream, tx_buffer),
+                                (rx_stream, rx_buffer),
+                            ));
+                        }
+                    };
+                    self.spi.cr2.modify(|_, w| w.txdmaen().set_bit());
+
+                    Ok(DmaTransfer { spi: self, tx, rx })
+                }
+
+                /// Starts sending `buffer` using DMA, discarding the received data
+                ///
+                /// Chip select is up to the caller, see `transfer_dma`. Fails with
+                /// `dma::Error::BufferLength` if `buffer` is empty or too long, returning the
+                /// resources.
+                #[allow(clippy::type_complexity)]
+                pub fn write_dma<STREAM, BUFFER>(
+                    self,
+                    buffer: BUFFER,
+                    stream: STREAM,
+                ) -> Result<DmaWrite<$SPIX, PINS, WORD, STREAM, BUFFER>, (Error, Self, STREAM, BUFFER)>
+                where
+                    STREAM: Stream,
+                    Tx<$SPIX, WORD>: Target<STREAM, MemoryToPeripheral, Word = WORD>,
+                    BUFFER: ReadBuffer<Word = WORD>,
+                {
+                    let tx = match Transfer::write(
+                        stream,
+                        Tx { _spi: PhantomData },
+                        buffer,
+                        dma::Config::default(),
+                    ) {
+                        Ok(tx) => tx,
+                        Err((error, stream, _, buffer)) => {
+                            return Err((Error::Dma(error), self, stream, buffer));
+                        }
+                    };
+                    self.spi.cr2.modify(|_, w| w.txdmaen().set_bit());
+
+                    Ok(DmaWrite { spi: self, tx })
+                }
+
+                fn stop_interrupts(&mut self) {
+                    self.unlisten(Event::Rxne);
+                    self.unlisten(Event::Txe);
+                    self.unlisten(Event::Error);
+                }
+
+                fn is_idle(&self) -> bool {
                     let sr = self.spi.sr.read();
+                    sr.ftlvl().bits() == 0 && sr.bsy().bit_is_clear()
+                }
 
-                    Err(if sr.ovr().bit_is_set() {
-                        nb::Error::Other(Error::Overrun)
-                    } else if sr.modf().bit_is_set() {
-                        nb::Error::Other(Error::ModeFault)
-                    } else if sr.crcerr().bit_is_set() {
-                        nb::Error::Other(Error::Crc)
-                    } else if sr.rxne().bit_is_set() {
-                        // NOTE(read_volatile) read only 1 byte (the svd2rust API only allows
-                        // reading a half-word)
-                        return Ok(unsafe {
-                            ptr::read_volatile(&self.spi.dr as *const _ as *const u8)
-                        });
+                /// Waits for the last frame to be sent and stops the DMA requests
+                fn finish_dma(&mut self) {
+                    while !self.is_idle() {}
+
+                    self.spi
+                        .cr2
+                        .modify(|_, w| w.txdmaen().clear_bit().rxdmaen().clear_bit());
+
+                    // Drop the data received by transmit only transfers, then clear the overrun
+                    // this caused by reading SR after DR
+                    while self.spi.sr.read().frlvl().bits() != 0 {
+                        // NOTE(read_volatile) see `FullDuplex::read`
+                        unsafe { ptr::read_volatile(&self.spi.dr as *const _ as *const u8) };
+                    }
+                    self.spi.sr.read();
+                }
+            }
+
+            impl<PINS, WORD> Spi<$SPIX, PINS, WORD>
+            where
+                WORD: Word,
+                Self: FullDuplex<WORD, Error = Error>,
+            {
+                /// Starts exchanging the contents of `buffer` for the received frames, driven by
+                /// the SPI interrupt
+                ///
+                /// The SPI interrupt has to call `InterruptTransfer::poll` until the transfer is
+                /// complete. Chip select is up to the caller, see `transfer_dma`.
+                pub fn transfer_interrupt<BUFFER>(
+                    self,
+                    mut buffer: BUFFER,
+                ) -> InterruptTransfer<$SPIX, PINS, WORD, BUFFER>
+                where
+                    BUFFER: AsMut<[WORD]>,
+                {
+                    let len = buffer.as_mut().len();
+                    let mut transfer = InterruptTransfer {
+                        spi: self,
+                        buffer,
+                        len,
+                        sent: 0,
+                        received: 0,
+                    };
+
+                    // TXE fires right away, so the first frames are sent by the first `poll`
+                    transfer.spi.listen(Event::Rxne);
+                    transfer.spi.listen(Event::Error);
+                    transfer.spi.listen(Event::Txe);
+
+                    transfer
+                }
+            }
+
+            impl<PINS, WORD, BUFFER> InterruptTransfer<$SPIX, PINS, WORD, BUFFER>
+            where
+                WORD: Word,
+                Spi<$SPIX, PINS, WORD>: FullDuplex<WORD, Error = Error>,
+                BUFFER: AsMut<[WORD]>,
+            {
+                /// Moves the transfer forward, to be called from the SPI interrupt
+                ///
+                /// Returns `Ok` once all frames have been exchanged and `WouldBlock` while the
+                /// transfer is ongoing. Errors stop the transfer.
+                pub fn poll(&mut self) -> nb::Result<(), Error> {
+                    let buffer = self.buffer.as_mut();
+
+                    loop {
+                        let mut progress = false;
+
+                        if self.received < self.sent {
+                            match self.spi.read() {
+                                Ok(word) => {
+                                    buffer[self.received] = word;
+                                    self.received += 1;
+                                    progress = true;
+                                }
+                                Err(nb::Error::WouldBlock) => {}
+                                Err(error) => {
+                                    self.spi.stop_interrupts();
+                                    return Err(error);
+                                }
+                            }
+                        }
+
+                        if self.sent < self.len && self.sent - self.received < FRAMES_IN_FLIGHT
+                        {
+                            match self.spi.send(buffer[self.sent]) {
+                                Ok(()) => {
+                                    self.sent += 1;
+                                    progress = true;
+                                }
+                                Err(nb::Error::WouldBlock) => {}
+                                Err(error) => {
+                                    self.spi.stop_interrupts();
+                                    return Err(error);
+                                }
+                            }
+                        }
+
+                        if !progress {
+                            break;
+                        }
+                    }
+
+                    // TXE is only needed while the transmit FIFO holds back frames that could be
+                    // sent, otherwise it would keep firing
+                    if self.sent < self.len && self.sent - self.received < FRAMES_IN_FLIGHT {
+                        self.spi.listen(Event::Txe);
                     } else {
-                        nb::Error::WouldBlock
-                    })
+                        self.spi.unlisten(Event::Txe);
+                    }
+
+                    if self.received == self.len {
+                        self.spi.stop_interrupts();
+                        Ok(())
+                    } else {
+                        Err(nb::Error::WouldBlock)
+                    }
                 }
 
-                fn send(&mut self, byte: u8) -> nb::Result<(), Error> {
+                /// Returns `true` once all frames have been exchanged
+                pub fn is_complete(&self) -> bool {
+                    self.received == self.len
+                }
+
+                /// Releases the SPI and the buffer
+                ///
+                /// An incomplete transfer is stopped, but frames that have already been sent may
+                /// still be in progress.
+                pub fn release(mut self) -> (Spi<$SPIX, PINS, WORD>, BUFFER) {
+                    self.spi.stop_interrupts();
+                    (self.spi, self.buffer)
+                }
+            }
+
+            impl<PINS, WORD, TXSTREAM, RXSTREAM, TXBUFFER, RXBUFFER>
+                DmaTransfer<$SPIX, PINS, WORD, TXSTREAM, RXSTREAM, TXBUFFER, RXBUFFER>
+            where
+                WORD: Word,
+                TXSTREAM: Stream,
+                RXSTREAM: Stream,
+                Tx<$SPIX, WORD>: Target<TXSTREAM, MemoryToPeripheral>,
+                Rx<$SPIX, WORD>: Target<RXSTREAM, PeripheralToMemory>,
+            {
+                /// Returns `true` once all frames have been sent and received
+                pub fn is_complete(&self) -> bool {
+                    self.rx.is_complete()
+                }
+
+                /// Blocks until the transfer is complete and releases the SPI, the streams and
+                /// the buffers
+                ///
+                /// On error the transfer is stopped and the resources are returned along with
+                /// the error.
+                #[allow(clippy::type_complexity)]
+                pub fn wait(
+                    self,
+                ) -> Result<
+                    (Spi<$SPIX, PINS, WORD>, (TXSTREAM, TXBUFFER), (RXSTREAM, RXBUFFER)),
+                    (Error, Spi<$SPIX, PINS, WORD>, (TXSTREAM, TXBUFFER), (RXSTREAM, RXBUFFER)),
+                > {
+                    let DmaTransfer { mut spi, tx, rx } = self;
+
+                    let (error, tx, rx) = match tx.wait() {
+                        Ok((stream, _, buffer)) => match rx.wait() {
+                            Ok((rx_stream, _, rx_buffer)) => {
+                                (None, (stream, buffer), (rx_stream, rx_buffer))
+                            }
+                            Err((error, rx_stream, _, rx_buffer)) => {
+                                (Some(error), (stream, buffer), (rx_stream, rx_buffer))
+                            }
+                        },
+                        Err((error, stream, _, buffer)) => {
+                            let (rx_stream, _, rx_buffer, _) = rx.abort();
+                            (Some(error), (stream, buffer), (rx_stream, rx_buffer))
+                        }
+                    };
+                    spi.finish_dma();
+
+                    match error {
+                        None => Ok((spi, tx, rx)),
+                        Some(error) => Err((Error::Dma(error), spi, tx, rx)),
+                    }
+                }
+            }
+
+            impl<PINS, WORD, STREAM, BUFFER> DmaWrite<$SPIX, PINS, WORD, STREAM, BUFFER>
+            where
+                WORD: Word,
+                STREAM: Stream,
+                Tx<$SPIX, WORD>: Target<STREAM, MemoryToPeripheral>,
+            {
+                /// Returns `true` once all frames have been sent
+                pub fn is_complete(&self) -> bool {
+                    self.tx.is_complete() && self.spi.is_idle()
+                }
+
+                /// Blocks until the transfer is complete and releases the SPI, the stream and
+                /// the buffer
+                ///
+                /// On error the transfer is stopped and the resources are returned along with
+                /// the error.
+                #[allow(clippy::type_complexity)]
+                pub fn wait(
+                    self,
+                ) -> Result<(Spi<$SPIX, PINS, WORD>, STREAM, BUFFER), (Error, Spi<$SPIX, PINS, WORD>, STREAM, BUFFER)>
+                {
+                    let DmaWrite { mut spi, tx } = self;
+
+                    let result = tx.wait();
+                    spi.finish_dma();
+
+                    match result {
+                        Ok((stream, _, buffer)) => Ok((spi, stream, buffer)),
+                        Err((error, stream, _, buffer)) => Err((Error::Dma(error), spi, stream, buffer)),
+                    }
+                }
+            }
+
+            impl<PINS, WORD> Spi<$SPIX, PINS, WORD> {
+                /// Enables a receive only master on the first read, which starts the clock
+                fn start_rx_only(&self) {
+                    let cr1 = self.spi.cr1.read();
+                    if cr1.rxonly().bit_is_set()
+                        && cr1.mstr().bit_is_set()
+                        && cr1.spe().bit_is_clear()
+                    {
+                        self.spi.cr1.modify(|_, w| w.spe().set_bit());
+                    }
+                }
+
+                /// Returns `Ok` once the flag selected by `ready` is set, unless an error occurred
+                fn check(&self, ready: bool) -> nb::Result<(), Error> {
                     let sr = self.spi.sr.read();
 
                     Err(if sr.ovr().bit_is_set() {
@@ -739,20 +1159,62 @@ macro_rules! hal {
                         nb::Error::Other(Error::ModeFault)
                     } else if sr.crcerr().bit_is_set() {
                         nb::Error::Other(Error::Crc)
-                    } else if sr.txe().bit_is_set() {
-                        // NOTE(write_volatile) see note above
-                        unsafe { ptr::write_volatile(&self.spi.dr as *const _ as *mut u8, byte) }
+                    } else if ready {
                         return Ok(());
                     } else {
                         nb::Error::WouldBlock
                     })
                 }
+            }
+
+            impl<PINS> hal::spi::FullDuplex<u8> for Spi<$SPIX, PINS, u8> {
+                type Error = Error;
+
+                fn read(&mut self) -> nb::Result<u8, Error> {
+                    self.start_rx_only();
+                    self.check(self.spi.sr.read().rxne().bit_is_set())?;
+
+                    // NOTE(read_volatile) read only 1 byte (the svd2rust API only allows
+                    // reading a half-word, which would take two frames from the FIFO)
+                    Ok(unsafe { ptr::read_volatile(&self.spi.dr as *const _ as *const u8) })
+                }
+
+                fn send(&mut self, byte: u8) -> nb::Result<(), Error> {
+                    self.check(self.spi.sr.read().txe().bit_is_set())?;
+
+                    // NOTE(write_volatile) see note above
+                    unsafe { ptr::write_volatile(ptr::addr_of!(self.spi.dr) as *mut u8, byte) }
+                    Ok(())
+                }
+            }
 
+            impl<PINS> hal::spi::FullDuplex<u16> for Spi<$SPIX, PINS, u16> {
+                type Error = Error;
+
+                fn read(&mut self) -> nb::Result<u16, Error> {
+                    self.start_rx_only();
+                    self.check(self.spi.sr.read().rxne().bit_is_set())?;
+
+                    // NOTE(read_volatile) read exactly one half-word
+                    Ok(unsafe { ptr::read_volatile(&self.spi.dr as *const _ as *const u16) })
+                }
+
+                fn send(&mut self, word: u16) -> nb::Result<(), Error> {
+                    self.check(self.spi.sr.read().txe().bit_is_set())?;
+
+                    // NOTE(write_volatile) see note above
+                    unsafe { ptr::write_volatile(ptr::addr_of!(self.spi.dr) as *mut u16, word) }
+                    Ok(())
+                }
             }
 
-            impl<PINS> ::hal::blocking::spi::transfer::Default<u8> for Spi<$SPIX, PINS> {}
+            impl<PINS> ::hal::blocking::spi::transfer::Default<u8> for Spi<$SPIX, PINS, u8> {}
+
+            impl<PINS> ::hal::blocking::spi::write::Default<u8> for Spi<$SPIX, PINS, u8> {}
+
+            impl<PINS> ::hal::blocking::spi::transfer::Default<u16> for Spi<$SPIX, PINS, u16> {}
 
-            impl<PINS> ::hal::blocking::spi::write::Default<u8> for Spi<$SPIX, PINS> {}
+            impl<PINS> ::hal::blocking::spi::write::Default<u16> for Spi<$SPIX, PINS, u16> {}
         )+
     }
 }
@@ -766,8 +1228,8 @@ macro_rules! hal {
     feature = "stm32f7x9",
 ))]
 hal! {
-    SPI1: (spi1, apb2enr, spi1en, pclk2),
-    SPI2: (spi2, apb1enr, spi2en, pclk1),
+    SPI1: (spi1, spi1_slave, APB2, pclk2),
+    SPI2: (spi2, spi2_slave, APB1, pclk1),
 }
 #[cfg(any(
     feature = "stm32f7x2",
@@ -778,7 +1240,7 @@ hal! {
     feature = "stm32f7x9",
 ))]
 hal! {
-    SPI3: (spi3, apb1enr, spi3en, pclk1),
+    SPI3: (spi3, spi3_slave, APB1, pclk1),
 }
 #[cfg(any(
     feature = "stm32f7x2",
@@ -789,7 +1251,7 @@ hal! {
     feature = "stm32f7x9",
 ))]
 hal! {
-    SPI4: (spi4, apb2enr, spi4en, pclk2),
+    SPI4: (spi4, spi4_slave, APB2, pclk2),
 }
 #[cfg(any(
     feature = "stm32f7x2",
@@ -800,8 +1262,41 @@ hal! {
     feature = "stm32f7x9",
 ))]
 hal! {
-    SPI5: (spi5, apb2enr, spi5en, pclk2),
+    SPI5: (spi5, spi5_slave, APB2, pclk2),
 }
+#[cfg(any(
+    feature = "stm32f7x5",
+    feature = "stm32f7x6",
+    feature = "stm32f7x7",
+    feature = "stm32f7x9",
+))]
+hal! {
+    SPI6: (spi6, spi6_slave, APB2, pclk2),
+}
+
+macro_rules! dma_targets {
+    ($(
+        $TARGET:ident<$SPIX:ident>: ($DIR:ident, [$($STREAM:ty: $CHANNEL:ty),+]),
+    )+) => {
+        $(
+            $(
+                unsafe impl<WORD> Target<$STREAM, $DIR> for $TARGET<$SPIX, WORD>
+                where
+                    WORD: Word,
+                {
+                    type Channel = $CHANNEL;
+                    type Word = WORD;
+
+                    fn address(&self) -> u32 {
+                        // NOTE(unsafe) only the address of the register is taken
+                        unsafe { &(*$SPIX::ptr()).dr as *const _ as u32 }
+                    }
+                }
+            )+
+        )+
+    }
+}
+
 #[cfg(any(
     feature = "stm32f7x2",
     feature = "stm32f7x3",
@@ -810,6 +1305,26 @@ hal! {
     feature = "stm32f7x7",
     feature = "stm32f7x9",
 ))]
-hal! {
-    SPI6: (spi6, apb2enr, spi6en, pclk2),
+dma_targets! {
+    Tx<SPI1>: (MemoryToPeripheral, [dma2::S3: C3, dma2::S5: C3]),
+    Rx<SPI1>: (PeripheralToMemory, [dma2::S0: C3, dma2::S2: C3]),
+    Tx<SPI2>: (MemoryToPeripheral, [dma1::S4: C0]),
+    Rx<SPI2>: (PeripheralToMemory, [dma1::S3: C0]),
+    Tx<SPI3>: (MemoryToPeripheral, [dma1::S5: C0, dma1::S7: C0]),
+    Rx<SPI3>: (PeripheralToMemory, [dma1::S0: C0, dma1::S2: C0]),
+    Tx<SPI4>: (MemoryToPeripheral, [dma2::S1: C4, dma2::S4: C5]),
+    Rx<SPI4>: (PeripheralToMemory, [dma2::S0: C4, dma2::S3: C5]),
+    Tx<SPI5>: (MemoryToPeripheral, [dma2::S4: C2, dma2::S6: C7]),
+    Rx<SPI5>: (PeripheralToMemory, [dma2::S3: C2, dma2::S5: C7]),
+}
+
+#[cfg(any(
+    feature = "stm32f7x5",
+    feature = "stm32f7x6",
+    feature = "stm32f7x7",
+    feature = "stm32f7x9",
+))]
+dma_targets! {
+    Tx<SPI6>: (MemoryToPeripheral, [dma2::S5: C1]),
+    Rx<SPI6>: (PeripheralToMemory, [dma2::S6: C1]),
 }
@@ -7,7 +7,6 @@ use hal::timer::{CountDown, Periodic};
 use nb;
 use void::Void;
 
-use stm32::RCC;
 #[cfg(any(
     feature = "stm32f7x2",
     feature = "stm32f7x3",
@@ -45,7 +44,7 @@ use stm32::{TIM10, TIM2, TIM3, TIM4};
 ))]
 use stm32::{TIM12, TIM13, TIM14, TIM7, TIM8};
 
-use rcc::Clocks;
+use rcc::{Clocks, Enable, Reset, APB1, APB2};
 use time::Hertz;
 
 /// Hardware timers
@@ -115,19 +114,17 @@ impl CountDown for Timer<SYST> {
 impl Periodic for Timer<SYST> {}
 
 macro_rules! hal {
-    ($($TIM:ident: ($tim:ident, $timXen:ident, $timXrst:ident, $apbenr:ident, $apbrstr:ident, $pclk:ident, $ppre:ident),)+) => {
+    ($($TIM:ident: ($tim:ident, $APB:ident, $pclk:ident, $ppre:ident),)+) => {
         $(
             impl Timer<$TIM> {
                 /// Configures a TIM peripheral as a periodic count down timer
-                pub fn $tim<T>(tim: $TIM, timeout: T, clocks: Clocks) -> Self
+                pub fn $tim<T>(tim: $TIM, timeout: T, clocks: Clocks, apb: &mut $APB) -> Self
                 where
                     T: Into<Hertz>,
                 {
                     // enable and reset peripheral to a clean slate state
-                    let rcc = unsafe { &(*RCC::ptr()) };
-                    rcc.$apbenr.modify(|_, w| w.$timXen().set_bit());
-                    rcc.$apbrstr.modify(|_, w| w.$timXrst().set_bit());
-                    rcc.$apbrstr.modify(|_, w| w.$timXrst().clear_bit());
+                    $TIM::enable(apb);
+                    $TIM::reset(apb);
 
                     let mut timer = Timer {
                         clocks,
@@ -216,10 +213,10 @@ macro_rules! hal {
     feature = "stm32f7x9",
 ))]
 hal! {
-    TIM1: (tim1, tim1en, tim1rst, apb2enr, apb2rstr, pclk2, ppre2),
-    TIM5: (tim5, tim5en, tim5rst, apb1enr, apb1rstr, pclk1, ppre1),
-    TIM9: (tim9, tim9en, tim9rst, apb2enr, apb2rstr, pclk1, ppre1),
-    TIM11: (tim11, tim11en, tim11rst, apb2enr, apb2rstr, pclk2, ppre2),
+    TIM1: (tim1, APB2, pclk2, ppre2),
+    TIM5: (tim5, APB1, pclk1, ppre1),
+    TIM9: (tim9, APB2, pclk2, ppre2),
+    TIM11: (tim11, APB2, pclk2, ppre2),
 }
 
 #[cfg(any(
@@ -231,10 +228,10 @@ hal! {
     feature = "stm32f7x9",
 ))]
 hal! {
-    TIM2: (tim2, tim2en, tim2rst, apb1enr, apb1rstr, pclk1, ppre1),
-    TIM3: (tim3, tim3en, tim3rst, apb1enr, apb1rstr, pclk1, ppre1),
-    TIM4: (tim4, tim4en, tim4rst, apb1enr, apb1rstr, pclk1, ppre1),
-    TIM10: (tim10, tim10en, tim10rst, apb2enr, apb2rstr, pclk2, ppre2),
+    TIM2: (tim2, APB1, pclk1, ppre1),
+    TIM3: (tim3, APB1, pclk1, ppre1),
+    TIM4: (tim4, APB1, pclk1, ppre1),
+    TIM10: (tim10, APB2, pclk2, ppre2),
 }
 
 #[cfg(any(
@@ -246,7 +243,7 @@ hal! {
     feature = "stm32f7x9",
 ))]
 hal! {
-    TIM6: (tim6, tim6en, tim6rst, apb1enr, apb1rstr, pclk1, ppre1),
+    TIM6: (tim6, APB1, pclk1, ppre1),
 }
 
 #[cfg(any(
@@ -258,9 +255,9 @@ hal! {
     feature = "stm32f7x9",
 ))]
 hal! {
-    TIM7: (tim7, tim7en, tim7rst, apb1enr, apb1rstr, pclk1, ppre1),
-    TIM8: (tim8, tim8en, tim8rst, apb2enr, apb2rstr, pclk2, ppre2),
-    TIM12: (tim12, tim12en, tim12rst, apb1enr, apb1rstr, pclk1, ppre1),
-    TIM13: (tim13, tim13en, tim13rst, apb1enr, apb1rstr, pclk1, ppre1),
-    TIM14: (tim14, tim14en, tim14rst, apb1enr, apb1rstr, pclk1, ppre1),
+    TIM7: (tim7, APB1, pclk1, ppre1),
+    TIM8: (tim8, APB2, pclk2, ppre2),
+    TIM12: (tim12, APB1, pclk1, ppre1),
+    TIM13: (tim13, APB1, pclk1, ppre1),
+    TIM14: (tim14, APB1, pclk1, ppre1),
 }
@@ -0,0 +1,796 @@
+//! Checks the register values programmed by the drivers for a range of clock configurations
+//!
+//! The drivers run against the simulated peripherals of the `mock` feature:
+//!
+//! `cargo test --target x86_64-unknown-linux-gnu --features stm32f7x6,mock`
+
+extern crate stm32f7xx_hal as hal;
+
+use std::cell::Cell;
+use std::ptr;
+use std::sync::Mutex;
+
+use hal::dma::{self, DmaExt, Transfer};
+use hal::i2c::{self, I2c, I2cSlave, OwnAddress, SlaveConfig, SlaveEvent, Timing};
+use hal::prelude::*;
+use hal::rcc::{Clocks, Rcc, UsartClock, AHB1, APB1, APB2, CFGR};
+use hal::serial::{config, Serial};
+use hal::spi::config::Config;
+use hal::spi::{self, Mode, Phase, Polarity, Spi};
+use hal::stm32::{
+    DMA1, DMA2, FLASH, GPIOA, GPIOB, I2C1, I2C2, RCC, SPI2, TIM1, TIM2, USART1, USART2,
+};
+use hal::time::Hertz;
+use hal::timer::Timer;
+
+/// Serializes the tests, which all share the simulated registers
+static LOCK: Mutex<()> = Mutex::new(());
+
+const HSI: u32 = 16_000_000;
+
+/// Clock configurations every driver is checked against
+const CONFIGURATIONS: &[fn(CFGR) -> CFGR] = &[
+    |cfgr| cfgr,
+    |cfgr| cfgr.sysclk(64.mhz()),
+    |cfgr| {
+        cfgr.sysclk(96.mhz())
+            .usart1_clock(UsartClock::Sysclk)
+            .usart2_clock(UsartClock::Hsi)
+    },
+    |cfgr| {
+        cfgr.use_hse(8.mhz())
+            .sysclk(180.mhz())
+            .pclk1(45.mhz())
+            .pclk2(90.mhz())
+    },
+    |cfgr| cfgr.use_hse(25.mhz()).sysclk(216.mhz()),
+    |cfgr| {
+        cfgr.use_hse(25.mhz())
+            .sysclk(100.mhz())
+            .hclk(50.mhz())
+            .pclk1(12_500.khz())
+    },
+];
+
+/// Runs `test` once for every clock configuration, starting from reset peripherals each time
+fn for_each_configuration<F>(mut test: F)
+where
+    F: FnMut(Clocks, &mut AHB1, &mut APB1, &mut APB2),
+{
+    let _lock = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    for configure in CONFIGURATIONS {
+        unsafe { hal::mock::reset() };
+
+        let Rcc {
+            mut ahb1,
+            mut apb1,
+            mut apb2,
+            cfgr,
+            ..
+        } = unsafe { RCC::steal() }.constrain();
+        let clocks = configure(cfgr).freeze().unwrap();
+
+        test(clocks, &mut ahb1, &mut apb1, &mut apb2);
+    }
+}
+
+#[test]
+fn rcc_registers_match_clocks() {
+    for_each_configuration(|clocks, _, _, _| {
+        let rcc = unsafe { &*RCC::ptr() };
+        let cfgr = rcc.cfgr.read();
+        let pllcfgr = rcc.pllcfgr.read();
+
+        let input = clocks.hse().map(|hse| hse.0).unwrap_or(HSI);
+        let sysclk = match cfgr.sw().bits() {
+            0b00 => HSI,
+            0b01 => input,
+            _ => {
+                let vco = u64::from(input) * u64::from(pllcfgr.plln().bits())
+                    / u64::from(pllcfgr.pllm().bits());
+                (vco / (2 * (u64::from(pllcfgr.pllp().bits()) + 1))) as u32
+            }
+        };
+        assert_eq!(Hertz(sysclk), clocks.sysclk());
+
+        let hpre = cfgr.hpre().bits();
+        let hclk = if hpre & 0b1000 == 0 {
+            sysclk
+        } else {
+            sysclk / [2, 4, 8, 16, 64, 128, 256, 512][usize::from(hpre & 0b111)]
+        };
+        assert_eq!(Hertz(hclk), clocks.hclk());
+
+        let ppre = |bits: u8| {
+            if bits & 0b100 == 0 {
+                1
+            } else {
+                2 << (bits & 0b11)
+            }
+        };
+        assert_eq!(Hertz(hclk / ppre(cfgr.ppre1().bits())), clocks.pclk1());
+        assert_eq!(Hertz(hclk / ppre(cfgr.ppre2().bits())), clocks.pclk2());
+
+        // One wait state per 30 MHz
+        let latency = unsafe { &*FLASH::ptr() }.acr.read().latency().bits();
+        assert_eq!(u32::from(latency), (sysclk - 1) / 30_000_000);
+    });
+}
+
+#[test]
+fn timer_prescaler_and_reload() {
+    for_each_configuration(|clocks, _, apb1, apb2| {
+        Timer::tim2(unsafe { TIM2::steal() }, 1.khz(), clocks, apb1);
+        Timer::tim1(unsafe { TIM1::steal() }, 10.hz(), clocks, apb2);
+
+        // Timers run at twice the APB frequency if the APB is divided
+        let tim2clk = clocks.pclk1().0 * if clocks.ppre1() == 1 { 1 } else { 2 };
+        let tim1clk = clocks.pclk2().0 * if clocks.ppre2() == 1 { 1 } else { 2 };
+
+        let tim2 = unsafe { &*TIM2::ptr() };
+        let tim1 = unsafe { &*TIM1::ptr() };
+        for &(psc, arr, clk, timeout) in &[
+            (
+                tim2.psc.read().bits(),
+                tim2.arr.read().bits(),
+                tim2clk,
+                1_000.0,
+            ),
+            (
+                tim1.psc.read().bits(),
+                tim1.arr.read().bits(),
+                tim1clk,
+                10.0,
+            ),
+        ] {
+            assert!(psc <= 0xffff && arr <= 0xffff);
+
+            let actual = f64::from(clk) / (f64::from(psc + 1) * f64::from(arr + 1));
+            assert!(
+                (actual - timeout).abs() / timeout < 0.001,
+                "{} Hz instead of {} Hz",
+                actual,
+                timeout
+            );
+        }
+    });
+}
+
+#[test]
+fn serial_baud_rate() {
+    for_each_configuration(|clocks, ahb1, apb1, apb2| {
+        let gpioa = unsafe { GPIOA::steal() }.split(ahb1);
+
+        Serial::usart1(
+            unsafe { USART1::steal() },
+            (
+                gpioa.pa9.into_alternate_af7(),
+                gpioa.pa10.into_alternate_af7(),
+            ),
+            config::Config::default().baudrate(115_200.bps()),
+            clocks,
+            apb2,
+        )
+        .unwrap();
+        Serial::usart2(
+            unsafe { USART2::steal() },
+            (
+                gpioa.pa2.into_alternate_af7(),
+                gpioa.pa3.into_alternate_af7(),
+            ),
+            config::Config::default().baudrate(115_200.bps()),
+            clocks,
+            apb1,
+        )
+        .unwrap();
+
+        // Oversampling by 16, rounded to the closest divider
+        for &(brr, clk) in &[
+            (
+                unsafe { &*USART1::ptr() }.brr.read().bits(),
+                clocks.usart1clk().0,
+            ),
+            (
+                unsafe { &*USART2::ptr() }.brr.read().bits(),
+                clocks.usart2clk().0,
+            ),
+        ] {
+            assert_eq!(brr, (clk + 115_200 / 2) / 115_200);
+        }
+    });
+}
+
+#[test]
+fn dma_transfers() {
+    static mut EMPTY: [u8; 0] = [];
+    static mut BUFFERS: [[u8; 4]; 2] = [[0; 4]; 2];
+    static mut SHORT: [u8; 2] = [0; 2];
+
+    for_each_configuration(|clocks, ahb1, _, apb2| {
+        let gpioa = unsafe { GPIOA::steal() }.split(ahb1);
+        let serial = Serial::usart1(
+            unsafe { USART1::steal() },
+            (
+                gpioa.pa9.into_alternate_af7(),
+                gpioa.pa10.into_alternate_af7(),
+            ),
+            config::Config::default(),
+            clocks,
+            apb2,
+        )
+        .unwrap();
+        let (_, rx) = serial.split();
+        let streams = unsafe { DMA2::steal() }.split(ahb1);
+        let regs = unsafe { &*DMA2::ptr() };
+
+        let empty: &'static mut [u8] = unsafe { &mut *ptr::addr_of_mut!(EMPTY) };
+        let (error, stream, rx, _) = rx.read_exact(empty, streams.s2).err().unwrap();
+        assert_eq!(error, dma::Error::BufferLength);
+        assert_eq!(regs.s2cr.read().bits() & 1, 0);
+
+        let [first, second] = unsafe { &mut *ptr::addr_of_mut!(BUFFERS) };
+        let short: &'static mut [u8] = unsafe { &mut *ptr::addr_of_mut!(SHORT) };
+        let (error, stream, rx, (first, _)) = Transfer::read_double_buffered(
+            stream,
+            rx,
+            (&mut first[..], short),
+            dma::Config::default(),
+        )
+        .err()
+        .unwrap();
+        assert_eq!(error, dma::Error::BufferLength);
+
+        let mut transfer = Transfer::read_double_buffered(
+            stream,
+            rx,
+            (first, &mut second[..]),
+            dma::Config::default(),
+        )
+        .ok()
+        .unwrap();
+        assert_eq!(regs.s2cr.read().bits() & 1, 1);
+
+        // The stream starts with the first buffer, leaving the second one to the caller. The
+        // addresses are truncated to 32 bits on the host.
+        let inactive = transfer.inactive_buffer().unwrap();
+        assert_eq!(inactive.as_ptr() as u32, regs.s2m1ar.read().bits());
+        assert_eq!(inactive.len(), 4);
+
+        // Dropping the transfer stops the stream
+        drop(transfer);
+        assert_eq!(regs.s2cr.read().bits() & 1, 0);
+    });
+}
+
+#[test]
+fn spi_baud_rate() {
+    let mode = Mode {
+        polarity: Polarity::IdleLow,
+        phase: Phase::CaptureOnFirstTransition,
+    };
+
+    for_each_configuration(|clocks, ahb1, apb1, _| {
+        let pclk1 = clocks.pclk1().0;
+
+        for div in 2..300 {
+            let gpioa = unsafe { GPIOA::steal() }.split(ahb1);
+            let pins = (
+                gpioa.pa9.into_alternate_af5(),
+                gpioa.pa12.into_alternate_af5(),
+                gpioa.pa10.into_alternate_af5(),
+            );
+
+            let requested = pclk1 / div;
+            let spi = Spi::spi2(
+                unsafe { SPI2::steal() },
+                pins,
+                mode,
+                Hertz(requested),
+                clocks,
+                apb1,
+            );
+
+            // The fastest SCK frequency that doesn't exceed the request, if there is one
+            let expected = (1..=8)
+                .map(|shift| pclk1 >> shift)
+                .find(|&f| f <= requested);
+            let spi = match (spi, expected) {
+                (Ok(spi), Some(expected)) => {
+                    assert_eq!(spi.actual_frequency(), Hertz(expected));
+                    spi
+                }
+                (Err(_), None) => continue,
+                (Ok(spi), None) => panic!("{:?} accepted", spi.actual_frequency()),
+                (Err(_), Some(expected)) => panic!("{} Hz rejected", expected),
+            };
+
+            let br = unsafe { &*SPI2::ptr() }.cr1.read().br().bits();
+            assert_eq!(spi.actual_frequency(), Hertz(pclk1 >> (br + 1)));
+        }
+
+        // Asking for no clock at all doesn't turn a master into a slave
+        let gpioa = unsafe { GPIOA::steal() }.split(ahb1);
+        let pins = (
+            gpioa.pa9.into_alternate_af5(),
+            gpioa.pa12.into_alternate_af5(),
+            gpioa.pa10.into_alternate_af5(),
+        );
+        let spi = Spi::spi2(unsafe { SPI2::steal() }, pins, mode, Hertz(0), clocks, apb1);
+        assert!(spi.is_err());
+    });
+}
+
+#[test]
+fn spi_config() {
+    for_each_configuration(|clocks, ahb1, apb1, _| {
+        let spi2 = unsafe { &*SPI2::ptr() };
+        let mut new_spi = |config: Config, apb1: &mut APB1| {
+            let gpioa = unsafe { GPIOA::steal() }.split(ahb1);
+            let pins = (
+                gpioa.pa9.into_alternate_af5(),
+                gpioa.pa12.into_alternate_af5(),
+                gpioa.pa10.into_alternate_af5(),
+            );
+            Spi::spi2(
+                unsafe { SPI2::steal() },
+                pins,
+                config,
+                1.mhz().into(),
+                clocks,
+                apb1,
+            )
+        };
+
+        // The CRC polynomial has to be odd
+        assert!(new_spi(Config::default().crc(0x1020), apb1).is_err());
+        new_spi(Config::default().crc(0x1021), apb1).unwrap();
+        assert_eq!(spi2.crcpr.read().crcpoly().bits(), 0x1021);
+        assert!(spi2.cr1.read().crcen().bit_is_set());
+
+        // A receive only master only starts clocking once it is read from
+        let mut spi = new_spi(Config::default().rx_only(), apb1).unwrap();
+        assert!(spi2.cr1.read().spe().bit_is_clear());
+        assert!(spi.read().is_err());
+        assert!(spi2.cr1.read().spe().bit_is_set());
+
+        // Frame sizes are limited to the ones fitting the word type
+        let spi = new_spi(Config::default(), apb1).unwrap();
+        assert!(spi.frame_size::<u16>(8).is_err());
+        let spi = new_spi(Config::default(), apb1).unwrap();
+        spi.frame_size::<u16>(12).unwrap();
+        assert_eq!(spi2.cr2.read().ds().bits(), 11);
+        assert!(spi2.cr1.read().crcl().bit_is_set());
+    });
+}
+
+#[test]
+fn spi_dma() {
+    static TX: [u8; 4] = [0; 4];
+    static mut RX: [u8; 4] = [0; 4];
+
+    for_each_configuration(|clocks, ahb1, apb1, _| {
+        let gpioa = unsafe { GPIOA::steal() }.split(ahb1);
+        let spi = Spi::spi2(
+            unsafe { SPI2::steal() },
+            (
+                gpioa.pa9.into_alternate_af5(),
+                gpioa.pa12.into_alternate_af5(),
+                gpioa.pa10.into_alternate_af5(),
+            ),
+            Config::default(),
+            1.mhz().into(),
+            clocks,
+            apb1,
+        )
+        .unwrap();
+        let streams = unsafe { DMA1::steal() }.split(ahb1);
+        let spi2 = unsafe { &*SPI2::ptr() };
+
+        let tx: &'static [u8] = &TX;
+        let rx: &'static mut [u8] = unsafe { &mut *ptr::addr_of_mut!(RX) };
+
+        // The buffers have to be of the same length
+        let (error, spi, (tx_stream, _), (rx_stream, rx)) = spi
+            .transfer_dma(&tx[..2], rx, streams.s4, streams.s3)
+            .err()
+            .unwrap();
+        assert!(matches!(error, spi::Error::Dma(dma::Error::BufferLength)));
+        assert_eq!(spi2.cr2.read().bits() & 0b11, 0);
+
+        let transfer = spi.transfer_dma(tx, rx, tx_stream, rx_stream).ok().unwrap();
+        assert!(spi2.cr2.read().rxdmaen().bit_is_set());
+        assert!(spi2.cr2.read().txdmaen().bit_is_set());
+        drop(transfer);
+    });
+}
+
+/// Checks `timing` against the I2C specification and returns the resulting SCL frequency
+///
+/// The times are in ns: the minimum data setup, the maximum data valid and the minimum SCL low and
+/// high times of the speed mode, followed by the rise and fall times of the bus.
+fn check_i2c_timing(i2cclk: u32, timing: Timing, mode: [f64; 4], rise: f64, fall: f64) -> f64 {
+    let [setup_min, valid_max, low_min, high_min] = mode;
+    // Delay of the analog filter
+    let (filter_min, filter_max) = (50.0, 260.0);
+
+    let tclk = 1e9 / f64::from(i2cclk);
+    let tpresc = f64::from(timing.presc + 1) * tclk;
+    assert!(timing.presc <= 0xf && timing.scldel <= 0xf && timing.sdadel <= 0xf);
+
+    let setup = f64::from(timing.scldel + 1) * tpresc - rise;
+    assert!(setup >= setup_min, "{:?}: data setup {} ns", timing, setup);
+
+    let hold = f64::from(timing.sdadel) * tpresc + filter_min + 3.0 * tclk - fall;
+    assert!(hold >= 0.0, "{:?}: data hold {} ns", timing, hold);
+    let valid = f64::from(timing.sdadel) * tpresc + rise + filter_max + 4.0 * tclk;
+    assert!(valid <= valid_max, "{:?}: data valid {} ns", timing, valid);
+
+    let low = f64::from(u16::from(timing.scll) + 1) * tpresc;
+    let high = f64::from(u16::from(timing.sclh) + 1) * tpresc;
+    assert!(low >= low_min, "{:?}: SCL low {} ns", timing, low);
+    assert!(high >= high_min, "{:?}: SCL high {} ns", timing, high);
+
+    let sync = fall + rise + 2.0 * (filter_min + 2.0 * tclk);
+    1e9 / (low + high + sync)
+}
+
+#[test]
+fn i2c_timing() {
+    const STANDARD: [f64; 4] = [250.0, 3450.0, 4700.0, 4000.0];
+    const FAST: [f64; 4] = [100.0, 900.0, 1300.0, 600.0];
+    const FAST_PLUS: [f64; 4] = [50.0, 450.0, 500.0, 260.0];
+
+    for &mhz in &[16, 25, 27, 32, 45, 48, 50, 54, 64, 96, 108, 180] {
+        let i2cclk = mhz * 1_000_000;
+
+        for &(speed, mode, rise, fall) in &[
+            (100_000, STANDARD, 1000, 300),
+            (50_000, STANDARD, 1000, 300),
+            (100_000, STANDARD, 100, 10),
+            (400_000, FAST, 300, 300),
+            (250_000, FAST, 300, 300),
+            (400_000, FAST, 100, 10),
+            (1_000_000, FAST_PLUS, 50, 20),
+        ] {
+            let timing = match Timing::new(Hertz(i2cclk), Hertz(speed), rise, fall) {
+                Ok(timing) => timing,
+                // The data hold and data valid times can't be both met at slow kernel clocks
+                Err(_) if speed == 1_000_000 && mhz < 30 => continue,
+                Err(_) => panic!("{} Hz at {} MHz rejected", speed, mhz),
+            };
+
+            let actual = check_i2c_timing(i2cclk, timing, mode, f64::from(rise), f64::from(fall));
+            assert!(
+                actual <= f64::from(speed) && actual > 0.8 * f64::from(speed),
+                "{} Hz instead of {} Hz at {} MHz",
+                actual,
+                speed,
+                mhz
+            );
+        }
+
+        match Timing::for_speed(Hertz(i2cclk), Hertz(1_000_000)) {
+            Ok(timing) => {
+                check_i2c_timing(i2cclk, timing, FAST_PLUS, 50.0, 20.0);
+            }
+            Err(_) => assert!(mhz < 30),
+        }
+    }
+
+    // The data setup time at the longest rise time exceeds what SCLDEL can delay
+    assert!(Timing::for_speed(Hertz(216_000_000), Hertz(100_000)).is_err());
+    assert!(Timing::for_speed(Hertz(54_000_000), Hertz(1_000_001)).is_err());
+    assert!(Timing::for_speed(Hertz(54_000_000), Hertz(0)).is_err());
+}
+
+#[test]
+fn i2c_timing_register() {
+    for_each_configuration(|clocks, ahb1, apb1, _| {
+        let gpiob = unsafe { GPIOB::steal() }.split(ahb1);
+        let i2c1 = I2c::i2c1(
+            unsafe { I2C1::steal() },
+            (
+                gpiob.pb8.into_alternate_af4(),
+                gpiob.pb9.into_alternate_af4(),
+            ),
+            100.khz(),
+            clocks,
+            apb1,
+        )
+        .unwrap();
+        let i2c2 = I2c::i2c2(
+            unsafe { I2C2::steal() },
+            (
+                gpiob.pb10.into_alternate_af4(),
+                gpiob.pb11.into_alternate_af4(),
+            ),
+            100.khz(),
+            clocks,
+            apb1,
+        )
+        .unwrap();
+
+        for &(i2c, clk) in &[
+            (unsafe { &*I2C1::ptr() }, clocks.i2c1clk()),
+            (unsafe { &*I2C2::ptr() }, clocks.i2c2clk()),
+        ] {
+            let timingr = i2c.timingr.read();
+            let timing = Timing::for_speed(clk, 100.khz().into()).unwrap();
+            assert_eq!(timingr.presc().bits(), timing.presc);
+            assert_eq!(timingr.scldel().bits(), timing.scldel);
+            assert_eq!(timingr.sdadel().bits(), timing.sdadel);
+            assert_eq!(timingr.sclh().bits(), timing.sclh);
+            assert_eq!(timingr.scll().bits(), timing.scll);
+            assert!(i2c.cr1.read().pe().bit_is_set());
+        }
+
+        i2c1.release();
+        i2c2.release();
+    });
+}
+
+#[test]
+fn i2c_timeout() {
+    use hal::hal::blocking::i2c::Write;
+
+    for_each_configuration(|clocks, ahb1, apb1, _| {
+        let gpiob = unsafe { GPIOB::steal() }.split(ahb1);
+        let mut i2c = I2c::i2c1(
+            unsafe { I2C1::steal() },
+            (
+                gpiob.pb6.into_alternate_af4(),
+                gpiob.pb7.into_alternate_af4(),
+            ),
+            100.khz(),
+            clocks,
+            apb1,
+        )
+        .unwrap();
+        let regs = unsafe { &*I2C1::ptr() };
+        let clk = u64::from(clocks.i2c1clk().0);
+
+        // The shortest timeout of at least 25 ms, detecting SCL low
+        let timeoutr = regs.timeoutr.read();
+        assert!(timeoutr.timouten().bit_is_set() && timeoutr.tidle().bit_is_clear());
+        let steps = u64::from(timeoutr.timeouta().bits()) + 1;
+        assert!(steps * 2048 * 1_000 >= 25 * clk);
+        assert!((steps - 1) * 2048 * 1_000 < 25 * clk);
+
+        i2c.set_timeout(None).unwrap();
+        assert!(regs.timeoutr.read().timouten().bit_is_clear());
+
+        // TIMEOUTA counts up to 4096 steps
+        let longest = (4096 * 2048 * 1_000_000 / clk) as u32;
+        i2c.set_timeout(Some(longest)).unwrap();
+        assert_eq!(regs.timeoutr.read().timeouta().bits(), 0xfff);
+        assert!(i2c.set_timeout(Some(longest + 1)).is_err());
+        assert!(i2c.set_timeout(Some(0)).is_err());
+
+        // Without any progress on the bus the transfer is given up on, resetting the peripheral
+        i2c.set_timeout(Some(1_000)).unwrap();
+        match Write::write(&mut i2c, 0x50u8, &[1]) {
+            Err(i2c::Error::Timeout) => {}
+            result => panic!("{:?}", result),
+        }
+        assert!(regs.cr1.read().pe().bit_is_set());
+
+        i2c.release();
+    });
+}
+
+/// Simulated bus lines, with a slave holding SDA low for a number of SCL clocks
+struct Lines {
+    clocks: Cell<u32>,
+    held: u32,
+}
+
+struct Scl<'a>(&'a Lines);
+struct Sda<'a>(&'a Lines);
+
+#[allow(deprecated)]
+impl<'a> hal::hal::digital::OutputPin for Scl<'a> {
+    fn set_high(&mut self) {}
+
+    fn set_low(&mut self) {
+        self.0.clocks.set(self.0.clocks.get() + 1);
+    }
+}
+
+#[allow(deprecated)]
+impl<'a> hal::hal::digital::OutputPin for Sda<'a> {
+    fn set_high(&mut self) {}
+
+    fn set_low(&mut self) {}
+}
+
+#[allow(deprecated)]
+impl<'a> hal::hal::digital::InputPin for Sda<'a> {
+    fn is_high(&self) -> bool {
+        !self.is_low()
+    }
+
+    fn is_low(&self) -> bool {
+        self.0.clocks.get() < self.0.held
+    }
+}
+
+struct NoDelay;
+
+impl hal::hal::blocking::delay::DelayUs<u32> for NoDelay {
+    fn delay_us(&mut self, _: u32) {}
+}
+
+#[test]
+fn i2c_bus_recovery() {
+    for held in 0..12 {
+        let lines = Lines {
+            clocks: Cell::new(0),
+            held,
+        };
+
+        // Nine clocks, followed by the one of the STOP condition
+        let result = i2c::recover_bus(&mut Scl(&lines), &mut Sda(&lines), &mut NoDelay);
+        match result {
+            Ok(()) => assert!(held <= 10),
+            Err(i2c::Error::Bus) => assert!(held > 10),
+            Err(e) => panic!("{:?}", e),
+        }
+
+        assert_eq!(lines.clocks.get(), held.min(9) + 1);
+    }
+}
+
+#[test]
+fn i2c_transfers() {
+    use hal::hal::blocking::i2c::{Read, Write, WriteIter, WriteRead};
+
+    for_each_configuration(|clocks, ahb1, apb1, _| {
+        let gpiob = unsafe { GPIOB::steal() }.split(ahb1);
+        let mut i2c = I2c::i2c1(
+            unsafe { I2C1::steal() },
+            (
+                gpiob.pb8.into_alternate_af4(),
+                gpiob.pb9.into_alternate_af4(),
+            ),
+            100.khz(),
+            clocks,
+            apb1,
+        )
+        .unwrap();
+        let regs = unsafe { &*I2C1::ptr() };
+
+        // Every step completes at once: TXIS, RXNE, STOPF, TC and TCR
+        regs.isr
+            .write(|w| unsafe { w.bits((1 << 1) | (1 << 2) | (1 << 5) | (1 << 6) | (1 << 7)) });
+
+        // The last transfer ends with a STOP condition
+        let mut buffer = [0; 3];
+        WriteRead::write_read(&mut i2c, 0x50u8, &[1, 2], &mut buffer).unwrap();
+        let cr2 = regs.cr2.read();
+        assert_eq!(cr2.sadd().bits(), 0x50 << 1);
+        assert!(cr2.add10().bit_is_clear() && cr2.head10r().bit_is_clear());
+        assert!(cr2.rd_wrn().bit_is_set() && cr2.start().bit_is_set());
+        assert_eq!(cr2.nbytes().bits(), 3);
+        assert!(cr2.autoend().bit_is_set() && cr2.reload().bit_is_clear());
+        assert_eq!(regs.txdr.read().txdata().bits(), 2);
+
+        // After writing to a 10-bit address, the read only repeats the header
+        WriteRead::write_read(&mut i2c, 0x2a5u16, &[1], &mut buffer).unwrap();
+        let cr2 = regs.cr2.read();
+        assert_eq!(cr2.sadd().bits(), 0x2a5);
+        assert!(cr2.add10().bit_is_set() && cr2.head10r().bit_is_set());
+        assert!(cr2.rd_wrn().bit_is_set());
+
+        Read::read(&mut i2c, 0x2a5u16, &mut buffer).unwrap();
+        let cr2 = regs.cr2.read();
+        assert!(cr2.add10().bit_is_set() && cr2.head10r().bit_is_clear());
+
+        Write::write(&mut i2c, 0x2a5u16, &[1, 2, 3]).unwrap();
+        let cr2 = regs.cr2.read();
+        assert!(cr2.add10().bit_is_set() && cr2.rd_wrn().bit_is_clear());
+        assert_eq!(cr2.nbytes().bits(), 3);
+
+        // Iterators are sent in chunks of up to 255 bytes
+        for &(len, last) in &[(0, 0), (1, 1), (255, 255), (256, 1), (600, 90)] {
+            WriteIter::write(&mut i2c, 0x50u8, (0..len).map(|i| i as u8)).unwrap();
+            let cr2 = regs.cr2.read();
+            assert_eq!(cr2.nbytes().bits(), last);
+            assert!(cr2.autoend().bit_is_set() && cr2.reload().bit_is_clear());
+            if len != 0 {
+                assert_eq!(regs.txdr.read().txdata().bits(), (len - 1) as u8);
+            }
+        }
+
+        i2c.release();
+    });
+}
+
+#[test]
+fn i2c_slave() {
+    for_each_configuration(|clocks, ahb1, apb1, _| {
+        // Own addresses have to fit in their registers
+        for &(address1, address2) in &[
+            (OwnAddress::SevenBit(0x80), None),
+            (OwnAddress::TenBit(0x400), None),
+            (OwnAddress::SevenBit(0x50), Some((0x80, 0))),
+            (OwnAddress::SevenBit(0x50), Some((0x48, 8))),
+        ] {
+            let gpiob = unsafe { GPIOB::steal() }.split(ahb1);
+            let slave = I2cSlave::i2c1(
+                unsafe { I2C1::steal() },
+                (
+                    gpiob.pb8.into_alternate_af4(),
+                    gpiob.pb9.into_alternate_af4(),
+                ),
+                SlaveConfig {
+                    address2,
+                    ..SlaveConfig::new(address1)
+                },
+                clocks,
+                apb1,
+            );
+            assert!(slave.is_err());
+        }
+
+        let gpiob = unsafe { GPIOB::steal() }.split(ahb1);
+        let mut slave = I2cSlave::i2c1(
+            unsafe { I2C1::steal() },
+            (
+                gpiob.pb8.into_alternate_af4(),
+                gpiob.pb9.into_alternate_af4(),
+            ),
+            SlaveConfig::new(OwnAddress::TenBit(0x2a5)).address2(0x48, 2),
+            clocks,
+            apb1,
+        )
+        .unwrap();
+        let regs = unsafe { &*I2C1::ptr() };
+
+        let oar1 = regs.oar1.read();
+        assert_eq!(oar1.oa1().bits(), 0x2a5);
+        assert!(oar1.oa1mode().bit_is_set() && oar1.oa1en().bit_is_set());
+        let oar2 = regs.oar2.read();
+        assert_eq!(oar2.oa2().bits(), 0x48);
+        assert_eq!(oar2.oa2msk().bits(), 2);
+        assert!(oar2.oa2en().bit_is_set());
+        let cr1 = regs.cr1.read();
+        assert!(cr1.pe().bit_is_set() && cr1.nostretch().bit_is_clear());
+
+        let mut event = |isr: u32| {
+            regs.isr.write(|w| unsafe { w.bits(isr) });
+            slave.poll()
+        };
+
+        // ADDR with ADDCODE, without and with DIR
+        assert_eq!(
+            event((1 << 3) | (0x49 << 17)).unwrap(),
+            SlaveEvent::WriteRequest { address: 0x49 }
+        );
+        // ICR is write only and RXDR read only on the hardware
+        let icr = ptr::addr_of!(regs.icr) as *const u32;
+        assert_ne!(unsafe { ptr::read_volatile(icr) } & (1 << 3), 0);
+        unsafe { ptr::write_volatile(ptr::addr_of!(regs.rxdr) as *mut u32, 0x42) };
+        assert_eq!(event(1 << 2).unwrap(), SlaveEvent::Received(0x42));
+        assert_eq!(
+            event((1 << 3) | (1 << 16) | (0x78 << 17)).unwrap(),
+            SlaveEvent::ReadRequest { address: 0x78 }
+        );
+        assert_eq!(event(1 << 1).unwrap(), SlaveEvent::TransmitRequest);
+        assert_eq!(event(1 << 5).unwrap(), SlaveEvent::Stop);
+        assert_ne!(unsafe { ptr::read_volatile(icr) } & (1 << 5), 0);
+        match event(0) {
+            Err(hal::nb::Error::WouldBlock) => {}
+            result => panic!("{:?}", result),
+        }
+        match event(1 << 8) {
+            Err(hal::nb::Error::Other(i2c::Error::Bus)) => {}
+            result => panic!("{:?}", result),
+        }
+
+        slave.send(0x17);
+        assert_eq!(regs.txdr.read().txdata().bits(), 0x17);
+
+        slave.release();
+    });
+}